@@ -1,44 +1,483 @@
-use std::sync::{
-    atomic::{AtomicBool, AtomicUsize, Ordering},
-    Arc,
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
-use async_recursion::async_recursion;
-use dashmap::DashMap;
+use dashmap::{mapref::entry::Entry, DashMap};
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt, StreamExt,
+};
+use ipnet::IpNet;
 use parking_lot::Mutex;
 use reqwest::{RequestBuilder, Url};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::watch;
+use zeroize::Zeroizing;
 
 #[cfg(feature = "cos")]
 use reqwest::header::{HeaderMap, HeaderValue};
+#[cfg(feature = "cos")]
+use std::sync::atomic::AtomicBool;
 
 use crate::{
+    cache::CacheStore,
     credentials::{Credential, Credentials},
-    dev::{self, CLIENT},
-    error::APIError,
+    dev,
+    error::{APIError, ApiErrorBody, LoginError, LoginFailure, LoginReport},
+    keyring::{KeyRing, KeyStats, Outcome, RingKey},
     models::{
         clan, clan_capital, clan_search, gold_pass, labels, leagues, location, paging, player,
         rankings, season, war, war_log,
     },
+    portal::{parse_cidr, DeveloperPortal, KeySettings},
+    ratelimit::TokenBucket,
+    retry::RetryPolicy,
+    secret::Secret,
+    session::{AccountSnapshot, SessionSnapshot},
+    transport::{HttpTransport, ReqwestTransport},
     util::LogicLong,
 };
 
-#[derive(Clone, Debug, Default)]
+/// The shared response of a coalesced request
+type InFlight = Shared<BoxFuture<'static, Result<String, APIError>>>;
+
+/// A re-login shared by every request that was rejected while it runs
+type Reinit = Shared<BoxFuture<'static, Result<(), APIError>>>;
+
+#[derive(Clone, Debug)]
 pub struct Client {
-    ready: Arc<AtomicBool>,
+    /// Whether the accounts are logged in, requests wait for this instead of failing outright
+    ready: Arc<watch::Sender<bool>>,
+    /// The re-login currently running, if any
+    reinit: Arc<Mutex<Option<Reinit>>>,
+    /// Bumped after every re-login, so that requests sent with keys it replaced don't start
+    /// another one
+    reinit_generation: Arc<AtomicUsize>,
+    pub(crate) config: Arc<ClientConfig>,
+    transport: Arc<dyn HttpTransport>,
+    /// Builds the requests, which are then sent through the transport
+    http: reqwest::Client,
+    cache: Option<Arc<dyn CacheStore>>,
+    /// GET requests currently being sent, by URL, so that identical ones can share a response
+    in_flight: Arc<DashMap<String, InFlight>>,
     pub(crate) accounts: Arc<DashMap<Credential, dev::APIAccount>>,
-
-    account_index: Arc<AtomicUsize>,
-    key_index: Arc<AtomicUsize>,
+    /// The keys requests are sent with, rebuilt whenever the accounts log in
+    key_ring: Arc<KeyRing>,
+    /// Whether the keys were passed to [`Client::with_tokens`] instead of created by the accounts
+    static_keys: bool,
+    /// Per-key token buckets, only used when a rate limit is configured
+    rate_limiters: Arc<DashMap<Secret, TokenBucket>>,
 
     ip_address: Arc<Mutex<String>>,
+    login_report: Arc<Mutex<LoginReport>>,
 
     #[cfg(feature = "cos")]
     pub(crate) is_cos_logged_in: Arc<AtomicBool>,
 }
 
+impl Default for Client {
+    fn default() -> Self {
+        Self::from_builder(ClientBuilder::default())
+    }
+}
+
+/// Settings shared by a [`Client`] and every developer account it logs into.
+#[derive(Clone, Debug)]
+pub(crate) struct ClientConfig {
+    pub(crate) base_url: String,
+    pub(crate) dev_base_url: String,
+    #[cfg(feature = "cos")]
+    pub(crate) cos_base_url: String,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) user_agent: Option<String>,
+    pub(crate) requests_per_second: Option<f64>,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) coalesce_requests: bool,
+    pub(crate) bulk_concurrency: Option<usize>,
+    pub(crate) key_settings: KeySettings,
+    pub(crate) account_key_settings: HashMap<String, KeySettings>,
+    pub(crate) partial_login: bool,
+    pub(crate) ready_timeout: Duration,
+    pub(crate) key_cooldown: Duration,
+    pub(crate) quarantine_after: u32,
+    pub(crate) ip_lookup_url: String,
+    pub(crate) refresh_margin: Duration,
+    pub(crate) egress_ips: Vec<IpNet>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            base_url: Client::BASE_URL.to_string(),
+            dev_base_url: DeveloperPortal::BASE_URL.to_string(),
+            #[cfg(feature = "cos")]
+            cos_base_url: Client::BASE_COS_URL.to_string(),
+            timeout: None,
+            user_agent: None,
+            requests_per_second: None,
+            retry_policy: RetryPolicy::none(),
+            coalesce_requests: true,
+            bulk_concurrency: None,
+            key_settings: KeySettings::default(),
+            account_key_settings: HashMap::new(),
+            partial_login: false,
+            ready_timeout: Duration::from_secs(30),
+            key_cooldown: Duration::from_secs(10),
+            quarantine_after: 3,
+            ip_lookup_url: Client::IP_LOOKUP_URL.to_string(),
+            refresh_margin: Duration::from_secs(5 * 60),
+            egress_ips: Vec::new(),
+        }
+    }
+}
+
+impl ClientConfig {
+    /// The key settings of the account with this email
+    pub(crate) fn key_settings(&self, email: &str) -> &KeySettings {
+        self.account_key_settings.get(email).unwrap_or(&self.key_settings)
+    }
+
+    /// The CIDR ranges keys are created for, the configured egress IP addresses or else the one
+    /// the portal saw the login from
+    pub(crate) fn egress_cidrs(&self, login_ip: &str) -> Vec<String> {
+        if self.egress_ips.is_empty() {
+            vec![login_ip.to_owned()]
+        } else {
+            self.egress_ips.iter().map(ToString::to_string).collect()
+        }
+    }
+}
+
+/// Builds a [`Client`] with custom base URLs, timeouts or user agent, e.g. to point it at a local
+/// stand-in for the official API.
+///
+/// # Example
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use coc_rs::{api::Client, credentials::Credentials};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let credentials = Credentials::builder()
+///         .add_credential("email".to_string(), "password".to_string())
+///         .build();
+///     let client = Client::builder()
+///         .base_url("http://localhost:8080/v1")
+///         .dev_base_url("http://localhost:8081")
+///         .timeout(Duration::from_secs(5))
+///         .build(credentials)
+///         .await?;
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ClientBuilder {
+    config: ClientConfig,
+    transport: Arc<dyn HttpTransport>,
+    cache: Option<Arc<dyn CacheStore>>,
+    /// The first invalid setting, reported when building the client
+    invalid: Option<String>,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self {
+            config: ClientConfig::default(),
+            transport: Arc::new(ReqwestTransport::default()),
+            cache: None,
+            invalid: None,
+        }
+    }
+}
+
+impl ClientBuilder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Transport used to send requests to the official API and Clash of Stats, defaults to
+    /// [`ReqwestTransport`]
+    #[must_use]
+    pub fn transport<T: HttpTransport + 'static>(mut self, transport: T) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    /// Base URL of the official API, defaults to `https://api.clashofclans.com/v1`
+    #[must_use]
+    pub fn base_url(mut self, url: &str) -> Self {
+        self.config.base_url = url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Base URL of the developer portal used to log in and manage keys, defaults to
+    /// `https://developer.clashofclans.com`
+    #[must_use]
+    pub fn dev_base_url(mut self, url: &str) -> Self {
+        self.config.dev_base_url = url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Base URL of the Clash of Stats API, defaults to `https://api.clashofstats.com`
+    #[cfg(feature = "cos")]
+    #[must_use]
+    pub fn cos_base_url(mut self, url: &str) -> Self {
+        self.config.cos_base_url = url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Timeout applied to every request, from connecting until the body has been read
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = Some(timeout);
+        self
+    }
+
+    /// User agent sent to the official API and the developer portal
+    #[must_use]
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.config.user_agent = Some(user_agent.to_string());
+        self
+    }
+
+    /// Limits how many requests per second each key may send, so that high-volume jobs wait for a
+    /// key with budget left instead of getting throttled by the API. Unlimited by default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `requests_per_second` isn't a positive number
+    #[must_use]
+    pub fn rate_limit(mut self, requests_per_second: f64) -> Self {
+        assert!(
+            requests_per_second.is_finite() && requests_per_second > 0.0,
+            "requests_per_second must be a positive number"
+        );
+        self.config.requests_per_second = Some(requests_per_second);
+        self
+    }
+
+    /// How requests that fail with a transient error are retried, defaults to
+    /// [`RetryPolicy::none`]
+    #[must_use]
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.config.retry_policy = retry_policy;
+        self
+    }
+
+    /// Caches responses of the official API for as long as their `Cache-Control` header allows,
+    /// e.g. in a [`MemoryCache`](crate::cache::MemoryCache). Disabled by default.
+    #[must_use]
+    pub fn cache<C: CacheStore + 'static>(mut self, cache: C) -> Self {
+        self.cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// Whether concurrent GET requests for the same URL share one response instead of each
+    /// sending their own, enabled by default
+    #[must_use]
+    pub const fn coalesce_requests(mut self, coalesce_requests: bool) -> Self {
+        self.config.coalesce_requests = coalesce_requests;
+        self
+    }
+
+    /// How many requests bulk methods like [`Client::get_players`] may have in flight at once,
+    /// defaults to the number of keys
+    #[must_use]
+    pub const fn bulk_concurrency(mut self, bulk_concurrency: usize) -> Self {
+        self.config.bulk_concurrency = Some(bulk_concurrency);
+        self
+    }
+
+    /// How many keys the client maintains on each account, how it names them, and which keys it
+    /// may revoke, defaults to [`KeySettings::default`]
+    #[must_use]
+    pub fn key_settings(mut self, key_settings: KeySettings) -> Self {
+        self.config.key_settings = key_settings;
+        self
+    }
+
+    /// Overrides [`ClientBuilder::key_settings`] for the account with this email
+    #[must_use]
+    pub fn key_settings_for(mut self, email: &str, key_settings: KeySettings) -> Self {
+        self.config.account_key_settings.insert(email.to_owned(), key_settings);
+        self
+    }
+
+    /// Whether the client starts with the accounts that logged in when others fail to, instead of
+    /// failing altogether, and counts logging in again as done once one account has. The failures
+    /// can be looked up with [`Client::login_report`]. Disabled by default.
+    #[must_use]
+    pub const fn partial_login(mut self, partial_login: bool) -> Self {
+        self.config.partial_login = partial_login;
+        self
+    }
+
+    /// How long requests wait for the client to log in, or to log in again after the IP address
+    /// changed, before failing with [`APIError::ClientNotReady`], defaults to 30 seconds
+    #[must_use]
+    pub const fn ready_timeout(mut self, ready_timeout: Duration) -> Self {
+        self.config.ready_timeout = ready_timeout;
+        self
+    }
+
+    /// How long a key that got throttled is skipped for, unless the API sent a `Retry-After`,
+    /// defaults to 10 seconds
+    #[must_use]
+    pub const fn key_cooldown(mut self, key_cooldown: Duration) -> Self {
+        self.config.key_cooldown = key_cooldown;
+        self
+    }
+
+    /// After how many 403s in a row a key is quarantined, i.e. no longer used and replaced with
+    /// a new one, defaults to 3
+    #[must_use]
+    pub fn quarantine_after(mut self, rejections: u32) -> Self {
+        self.config.quarantine_after = rejections.max(1);
+        self
+    }
+
+    /// IP address or CIDR range requests to the API leave from, e.g. `203.0.113.7` or
+    /// `203.0.113.0/24`, for when they leave through another NAT or proxy than the login. Call it
+    /// once per address to serve several, keys are then created for all of them. Defaults to the
+    /// address the developer portal sees the login from.
+    ///
+    /// Building the client fails with [`APIError::InvalidParameters`] if `ip` isn't an IP address
+    /// or CIDR range.
+    #[must_use]
+    pub fn egress_ip(mut self, ip: &str) -> Self {
+        match parse_cidr(ip) {
+            Some(network) if !self.config.egress_ips.contains(&network) => {
+                self.config.egress_ips.push(network);
+            }
+            Some(_) => {}
+            None => {
+                self.invalid.get_or_insert_with(|| format!("{ip} isn't an IP address"));
+            }
+        }
+        self
+    }
+
+    /// Calls [`ClientBuilder::egress_ip`] with every address
+    #[must_use]
+    pub fn egress_ips(self, ips: &[&str]) -> Self {
+        ips.iter().fold(self, |builder, ip| builder.egress_ip(ip))
+    }
+
+    /// URL that answers with the IP address requests come from in plain text, used by
+    /// [`Client::spawn_refresher`], defaults to `https://api.ipify.org`
+    #[must_use]
+    pub fn ip_lookup_url(mut self, url: &str) -> Self {
+        self.config.ip_lookup_url = url.to_string();
+        self
+    }
+
+    /// How long before an account's portal session or temporary API token expires
+    /// [`Client::spawn_refresher`] logs in again, defaults to 5 minutes
+    #[must_use]
+    pub const fn refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.config.refresh_margin = refresh_margin;
+        self
+    }
+
+    /// Builds the [`Client`] and logs in with every credential
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a setting is invalid
+    /// ([`APIError::InvalidParameters`]), if the credentials are invalid, or with
+    /// [`ClientBuilder::partial_login`] if none of them are ([`APIError::NoAccountsLoggedIn`])
+    pub async fn build(self, credentials: Credentials) -> anyhow::Result<Client> {
+        self.validate()?;
+        let client = Client::from_builder(self);
+        client.init(credentials).await?;
+        client.ready.send_replace(true);
+        Ok(client)
+    }
+
+    /// Builds the [`Client`] with the keys of a [`SessionSnapshot`], only logging into the
+    /// accounts that aren't in it. The other accounts log in once the API rejects their keys,
+    /// e.g. because the IP address changed.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a setting is invalid
+    /// ([`APIError::InvalidParameters`]), or if logging into an account that isn't in the snapshot
+    /// fails
+    pub async fn restore(
+        self,
+        credentials: Credentials,
+        snapshot: SessionSnapshot,
+    ) -> anyhow::Result<Client> {
+        self.validate()?;
+        let client = Client::from_builder(self);
+        *client.ip_address.lock() = snapshot.ip_address;
+
+        let mut missing = Credentials::empty();
+        for credential in credentials.0 {
+            let keys = snapshot
+                .accounts
+                .iter()
+                .find(|account| account.email == credential.email())
+                .map(AccountSnapshot::unexpired_keys)
+                .unwrap_or_default();
+            if keys.is_empty() {
+                missing.0.push(credential);
+            } else {
+                let account = dev::APIAccount::restore(credential, keys, client.config.clone());
+                client.accounts.insert(account.credential.clone(), account);
+            }
+        }
+
+        if !missing.0.is_empty() {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("{} accounts aren't in the snapshot, logging in", missing.0.len());
+            client.init(missing).await?;
+        }
+        client.rebuild_key_ring();
+        client.ready.send_replace(true);
+        Ok(client)
+    }
+
+    /// Builds the [`Client`] with pre-issued API keys, skipping the developer portal entirely.
+    ///
+    /// The keys must already be valid for this machine's IP address, since they can't be
+    /// recreated when the API rejects them.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a setting is invalid or no keys are passed in
+    pub fn build_with_tokens(self, tokens: Vec<String>) -> Result<Client, APIError> {
+        self.validate()?;
+        if tokens.is_empty() {
+            return Err(APIError::InvalidParameters(
+                "at least one API key is required".to_string(),
+            ));
+        }
+
+        let mut client = Client::from_builder(self);
+        client.key_ring = Arc::new(KeyRing::with_tokens(tokens));
+        client.static_keys = true;
+        client.ready.send_replace(true);
+        Ok(client)
+    }
+
+    fn validate(&self) -> Result<(), APIError> {
+        self.invalid.clone().map_or(Ok(()), |invalid| Err(APIError::InvalidParameters(invalid)))
+    }
+}
+
 impl Client {
     const BASE_URL: &'static str = "https://api.clashofclans.com/v1";
+    const IP_LOOKUP_URL: &'static str = "https://api.ipify.org";
 
     /// Returns a [`Client`]
     ///
@@ -46,60 +485,223 @@ impl Client {
     ///
     /// This function will return an error if the credentials are invalid
     pub async fn new(credentials: Credentials) -> anyhow::Result<Self> {
-        let client = Self {
-            ready: Arc::new(AtomicBool::new(false)),
+        ClientBuilder::new().build(credentials).await
+    }
 
-            accounts: Arc::new(DashMap::new()),
+    /// Returns a [`Client`] that rotates through pre-issued API keys instead of logging into the
+    /// developer portal
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if no keys are passed in
+    ///
+    /// # Example
+    /// ```no_run
+    /// use coc_rs::api::Client;
+    ///
+    /// let client = Client::with_tokens(vec!["eyJ0eXAiOiJKV1Qi...".to_string()])?;
+    /// ```
+    pub fn with_tokens(tokens: Vec<String>) -> Result<Self, APIError> {
+        ClientBuilder::new().build_with_tokens(tokens)
+    }
+
+    /// Returns a [`ClientBuilder`] to configure a [`Client`] before logging in
+    #[must_use]
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    fn from_builder(builder: ClientBuilder) -> Self {
+        Self {
+            ready: Arc::new(watch::channel(false).0),
+            reinit: Arc::new(Mutex::new(None)),
+            reinit_generation: Arc::new(AtomicUsize::new(0)),
+            config: Arc::new(builder.config),
+            transport: builder.transport,
+            http: reqwest::Client::new(),
+            cache: builder.cache,
+            in_flight: Arc::new(DashMap::new()),
 
-            account_index: Arc::new(AtomicUsize::new(0)),
-            key_index: Arc::new(AtomicUsize::new(0)),
+            accounts: Arc::new(DashMap::new()),
+            key_ring: Arc::new(KeyRing::default()),
+            static_keys: false,
+            rate_limiters: Arc::new(DashMap::new()),
 
             ip_address: Arc::new(Mutex::new(String::new())),
+            login_report: Arc::new(Mutex::new(LoginReport::default())),
 
             #[cfg(feature = "cos")]
             is_cos_logged_in: Arc::new(AtomicBool::new(false)),
-        };
-
-        client.init(credentials).await?;
-        client.ready.store(true, Ordering::Relaxed);
-        Ok(client)
+        }
     }
 
     /// Called when the client is created to initialize every credential.
     async fn init(&self, credentials: Credentials) -> anyhow::Result<()> {
-        let tasks = credentials.0.into_iter().map(dev::APIAccount::login);
-
-        let accounts =
-            futures::future::join_all(tasks).await.into_iter().collect::<Result<Vec<_>, _>>()?;
+        let emails = credentials.0.iter().map(|c| c.email().to_owned()).collect::<Vec<_>>();
+        let tasks = credentials
+            .0
+            .into_iter()
+            .map(|credential| dev::APIAccount::login(credential, self.config.clone()));
+
+        let mut accounts = Vec::new();
+        let mut report = LoginReport::default();
+        for (email, result) in emails.into_iter().zip(futures::future::join_all(tasks).await) {
+            match result {
+                Ok(account) => accounts.push(account),
+                Err(e) if self.config.partial_login => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!("failed to login as {}: {:#}", email, e);
+                    report.failures.push(login_failure(email, &e));
+                }
+                Err(e) => return Err(e),
+            }
+        }
 
-        *self.ip_address.lock() = accounts[0].1.clone();
+        // accounts restored from a snapshot count as well
+        if accounts.is_empty() && self.accounts.is_empty() {
+            return Err(APIError::NoAccountsLoggedIn(report).into());
+        }
+        if let Some((_, ip)) = accounts.first() {
+            *self.ip_address.lock() = ip.clone();
+        }
+        *self.login_report.lock() = report;
 
         for (account, _) in accounts {
             self.accounts.insert(account.credential.clone(), account);
         }
+        self.rebuild_key_ring();
 
         Ok(())
     }
 
-    /// Called when an IP address change is detected
-    pub(crate) async fn reinit(&self) -> anyhow::Result<()> {
+    /// Puts every account's keys in the rotation, interleaved so that consecutive requests are
+    /// spread over the accounts
+    fn rebuild_key_ring(&self) {
+        let mut accounts = self
+            .accounts
+            .iter()
+            .map(|account| {
+                let email = account.credential.email().to_owned();
+                let keys = account.keys.iter().take(DeveloperPortal::MAX_KEYS);
+                let keys = keys
+                    .map(|key| RingKey {
+                        id: key.id.clone(),
+                        account: Some(email.clone()),
+                        token: key.key.clone(),
+                    })
+                    .collect();
+                (email, keys)
+            })
+            .collect::<Vec<(String, Vec<RingKey>)>>();
+        // the map's order is arbitrary, keep the rotation stable between rebuilds
+        accounts.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let longest = accounts.iter().map(|(_, keys)| keys.len()).max().unwrap_or_default();
+        let keys = (0..longest)
+            .flat_map(|i| accounts.iter().filter_map(move |(_, keys)| keys.get(i).cloned()))
+            .collect::<Vec<_>>();
+        // revoked keys don't need a budget anymore
+        self.rate_limiters.retain(|token, _| keys.iter().any(|key| &key.token == token));
+        self.key_ring.replace(keys);
+    }
+
+    /// The credentials that failed to log in when the client was built with
+    /// [`ClientBuilder::partial_login`], or when it last logged in again, empty if all
+    /// of them succeeded
+    #[must_use]
+    pub fn login_report(&self) -> LoginReport {
+        self.login_report.lock().clone()
+    }
+
+    /// Called when an IP address change is detected. Concurrent calls share one re-login and its
+    /// outcome. The re-login runs on its own task, so it finishes even if every caller gives up on
+    /// it.
+    pub(crate) async fn reinit(&self) -> Result<(), APIError> {
+        let reinit = {
+            let mut current = self.reinit.lock();
+            if let Some(reinit) = current.as_ref() {
+                reinit.clone()
+            } else {
+                let client = self.clone();
+                let task = tokio::spawn(async move {
+                    let result = client.re_login().await.map_err(|e| login_error(&e));
+                    *client.reinit.lock() = None;
+                    result
+                });
+                let reinit = async move {
+                    task.await.unwrap_or_else(|e| {
+                        Err(APIError::LoginFailed(LoginError::PortalUnavailable(format!(
+                            "the re-login task failed: {e}"
+                        ))))
+                    })
+                }
+                .boxed()
+                .shared();
+                *current = Some(reinit.clone());
+                reinit
+            }
+        };
+
+        reinit.await
+    }
+
+    /// Logs into every account again, requests wait until it's done. Accounts that fail don't stop
+    /// the others and end up in the login report.
+    async fn re_login(&self) -> anyhow::Result<()> {
         #[cfg(feature = "tracing")]
         tracing::debug!("reinitializing client");
 
-        self.ready.store(false, Ordering::Relaxed);
+        self.ready.send_replace(false);
 
         let accounts = self.accounts.iter().map(|account| account.clone()).collect::<Vec<_>>();
-
+        let mut logged_in = 0;
+        let mut failed = Vec::new();
+        let mut report = LoginReport::default();
+        let mut result = Ok(());
         for mut account in accounts {
-            account.re_login().await?;
-
-            // update the account in the DashMap
-            self.accounts.insert(account.credential.clone(), account);
+            let email = account.credential.email().to_owned();
+            match account.re_login().await {
+                Ok(ip) => {
+                    // keys are compared against the new address from now on
+                    if logged_in == 0 {
+                        *self.ip_address.lock() = ip;
+                    }
+                    logged_in += 1;
+                    // update the account in the DashMap
+                    self.accounts.insert(account.credential.clone(), account);
+                }
+                // keep going, so that one bad credential doesn't leave the others on stale keys
+                Err(e) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!("failed to login again as {}: {:#}", email, e);
+                    report.failures.push(login_failure(email, &e));
+                    failed.push(account.credential);
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+        if logged_in > 0 {
+            // the failed accounts' keys are for the old address, sending requests with them would
+            // only log everyone in again. They get new keys once they log in, see `refresh`.
+            for credential in &failed {
+                if let Some(mut account) = self.accounts.get_mut(credential) {
+                    account.keys.clear();
+                }
+            }
+            if self.config.partial_login {
+                result = Ok(());
+            }
         }
+        *self.login_report.lock() = report;
+        self.rebuild_key_ring();
 
-        self.ready.store(true, Ordering::Relaxed);
+        self.reinit_generation.fetch_add(1, Ordering::AcqRel);
+        // even if it failed, so that requests get the API's error instead of waiting forever
+        self.ready.send_replace(true);
 
-        Ok(())
+        result
     }
 
     /// Here you can create a client yourself and load them here later (for example .env parsing)
@@ -128,12 +730,130 @@ impl Client {
         #[cfg(feature = "tracing")]
         tracing::trace!(credentials = ?credentials, "Loading credentials");
 
-        self.ready.store(false, Ordering::Relaxed);
+        self.ready.send_replace(false);
         self.init(credentials).await?;
-        self.ready.store(true, Ordering::Relaxed);
+        self.ready.send_replace(true);
         Ok(())
     }
 
+    /// Spawns a task that checks every `period` whether the IP address requests come from is
+    /// still allowed by the keys, logging in again and rotating them if it isn't, and logs into
+    /// accounts again whose session or temporary API token is about to expire. Keys are then
+    /// replaced before the API starts rejecting them.
+    ///
+    /// The task holds on to the client until it's aborted.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::time::Duration;
+    ///
+    /// let refresher = client.spawn_refresher(Duration::from_secs(60));
+    /// // when the client is no longer needed
+    /// refresher.abort();
+    /// ```
+    #[must_use = "the refresher runs until the handle is aborted"]
+    pub fn spawn_refresher(&self, period: Duration) -> tokio::task::JoinHandle<()> {
+        let client = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                #[cfg(feature = "tracing")]
+                if let Err(e) = client.refresh().await {
+                    tracing::warn!("failed to refresh the client: {:#}", e);
+                }
+                #[cfg(not(feature = "tracing"))]
+                let _ = client.refresh().await;
+            }
+        })
+    }
+
+    /// Logs in again if the IP address changed, otherwise only the accounts whose session or
+    /// temporary API token expires within the refresh margin, and those that failed to log in
+    /// again before
+    pub(crate) async fn refresh(&self) -> anyhow::Result<()> {
+        if self.static_keys {
+            return Ok(());
+        }
+
+        let egress = self.egress_ip().await?;
+        let cidrs = self.config.egress_cidrs(&self.ip_address.lock());
+        let allowed =
+            cidrs.iter().filter_map(|cidr| parse_cidr(cidr)).any(|ip| ip.contains(&egress));
+        if !allowed {
+            // logging in again won't change the configured addresses
+            if !self.config.egress_ips.is_empty() {
+                anyhow::bail!("requests leave from {egress}, which isn't a configured egress IP");
+            }
+            #[cfg(feature = "tracing")]
+            tracing::debug!("the IP address changed to {}, logging in again", egress);
+            return Ok(self.reinit().await?);
+        }
+
+        let expiring = self
+            .accounts
+            .iter()
+            .filter(|account| {
+                // accounts that failed to log in again have no keys left
+                account.keys.is_empty()
+                    || account
+                        .portal
+                        .as_ref()
+                        .is_some_and(|portal| portal.expires_in() <= self.config.refresh_margin)
+            })
+            .map(|account| account.clone())
+            .collect::<Vec<_>>();
+        if expiring.is_empty() {
+            return Ok(());
+        }
+
+        let mut result = Ok(());
+        for mut account in expiring {
+            match account.re_login().await {
+                Ok(ip) => {
+                    *self.ip_address.lock() = ip;
+                    self.accounts.insert(account.credential.clone(), account);
+                }
+                Err(e) => result = Err(e),
+            }
+        }
+        self.rebuild_key_ring();
+
+        result
+    }
+
+    /// The IP address requests come from, as seen by [`ClientBuilder::ip_lookup_url`]
+    async fn egress_ip(&self) -> Result<IpAddr, APIError> {
+        let url = Url::parse(&self.config.ip_lookup_url)?;
+        let request = self.apply_config(self.http.get(url)).build()?;
+        let response = self.transport.execute(request).await?;
+        let body = response.error_for_status()?.text().await?;
+        body.trim().parse().map_err(|_| APIError::FailedGetIP(body))
+    }
+
+    /// How the requests sent with each key went, in the order the keys are used
+    #[must_use]
+    pub fn key_stats(&self) -> Vec<KeyStats> {
+        self.key_ring.stats()
+    }
+
+    /// Exports the accounts' keys, to be passed to [`ClientBuilder::restore`] after a restart
+    #[must_use]
+    pub fn export_session(&self) -> SessionSnapshot {
+        SessionSnapshot {
+            ip_address: self.ip_address.lock().clone(),
+            accounts: self
+                .accounts
+                .iter()
+                .map(|account| AccountSnapshot {
+                    email: account.credential.email().to_owned(),
+                    keys: account.keys.clone(),
+                })
+                .collect(),
+        }
+    }
+
     /// This is purely for diagnostics, it's not used anywhere else.
     ///
     /// # Example
@@ -150,7 +870,7 @@ impl Client {
     #[cfg(feature = "tracing")]
     pub fn debug_keys(&self) {
         self.accounts.iter().for_each(|account| {
-            account.keys.keys.iter().for_each(|key| {
+            account.keys.iter().for_each(|key| {
                 tracing::debug!(key = %key.key, key.id=%key.id, key.name=%key.name);
             });
         });
@@ -164,10 +884,7 @@ impl Client {
         &self,
         url: U,
     ) -> Result<reqwest::RequestBuilder, APIError> {
-        if !self.ready.load(Ordering::Relaxed) {
-            return Err(APIError::ClientNotReady);
-        }
-        Ok(CLIENT.get(url).bearer_auth(self.get_next_key()))
+        Ok(self.apply_config(self.http.get(url)))
     }
 
     pub(crate) fn post<U: reqwest::IntoUrl, T: Into<reqwest::Body>>(
@@ -175,10 +892,23 @@ impl Client {
         url: U,
         body: T,
     ) -> Result<reqwest::RequestBuilder, APIError> {
-        if !self.ready.load(Ordering::Relaxed) {
-            return Err(APIError::ClientNotReady);
+        Ok(self.apply_config(self.http.post(url)).body(body))
+    }
+
+    /// Applies the configured timeout and user agent to a request for the official API
+    fn apply_config(&self, rb: RequestBuilder) -> RequestBuilder {
+        let rb = self.apply_timeout(rb);
+        match &self.config.user_agent {
+            Some(user_agent) => rb.header(reqwest::header::USER_AGENT, user_agent),
+            None => rb,
+        }
+    }
+
+    fn apply_timeout(&self, rb: RequestBuilder) -> RequestBuilder {
+        match self.config.timeout {
+            Some(timeout) => rb.timeout(timeout),
+            None => rb,
         }
-        Ok(CLIENT.post(url).bearer_auth(self.get_next_key()).body(body))
     }
 
     /// To allow usage without a client being ready
@@ -212,7 +942,7 @@ impl Client {
             HeaderValue::from_str("Mozilla/5.0 (X11; Windows x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/103.0.0.0 Safari/537.36")?,
         );
 
-        Ok(CLIENT.get(url).headers(headers))
+        Ok(self.apply_timeout(self.http.get(url)).headers(headers))
     }
 
     /// To allow usage without a client being ready
@@ -246,7 +976,7 @@ impl Client {
             HeaderValue::from_str("Mozilla/5.0 (X11; Windows x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/103.0.0.0 Safari/537.36")?,
         );
 
-        Ok(CLIENT.post(url).body(body).headers(headers))
+        Ok(self.apply_timeout(self.http.post(url)).body(body).headers(headers))
     }
 
     //         ╭──────────────────────────────────────────────────────────╮
@@ -263,8 +993,9 @@ impl Client {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_clan_warlog({})", clan_tag);
         let clan_tag = clan_tag.parse::<LogicLong>()?.to_string();
-        let url = format!("{}/clans/{}/warlog", Self::BASE_URL, urlencoding::encode(&clan_tag));
-        self.parse_json(self.get(url), false).await
+        let url =
+            format!("{}/clans/{}/warlog", self.config.base_url, urlencoding::encode(&clan_tag));
+        Self::private_war_log(self.parse_json(self.get(url), false).await)
     }
 
     /// # Errors
@@ -276,7 +1007,8 @@ impl Client {
     ) -> Result<APIResponse<clan::Clan>, APIError> {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_clans({})", options);
-        let url = Url::parse_with_params(&format!("{}/clans", Self::BASE_URL), options.items)?;
+        let url =
+            Url::parse_with_params(&format!("{}/clans", self.config.base_url), options.items)?;
         self.parse_json(self.get(url.to_string()), false).await
     }
 
@@ -287,8 +1019,9 @@ impl Client {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_current_war({})", clan_tag);
         let clan_tag = clan_tag.parse::<LogicLong>()?.to_string();
-        let url = format!("{}/clans/{}/currentwar", Self::BASE_URL, urlencoding::encode(&clan_tag));
-        self.parse_json(self.get(url), false).await
+        let url =
+            format!("{}/clans/{}/currentwar", self.config.base_url, urlencoding::encode(&clan_tag));
+        Self::private_war_log(self.parse_json(self.get(url), false).await)
     }
 
     /// # Errors
@@ -298,7 +1031,7 @@ impl Client {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_clan({})", clan_tag);
         let clan_tag = clan_tag.parse::<LogicLong>()?.to_string();
-        let url = format!("{}/clans/{}", Self::BASE_URL, urlencoding::encode(&clan_tag));
+        let url = format!("{}/clans/{}", self.config.base_url, urlencoding::encode(&clan_tag));
         self.parse_json(self.get(url), false).await
     }
 
@@ -312,7 +1045,8 @@ impl Client {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_clan_members({})", clan_tag);
         let clan_tag = clan_tag.parse::<LogicLong>()?.to_string();
-        let url = format!("{}/clans/{}/members", Self::BASE_URL, urlencoding::encode(&clan_tag));
+        let url =
+            format!("{}/clans/{}/members", self.config.base_url, urlencoding::encode(&clan_tag));
         self.parse_json(self.get(url), false).await
     }
 
@@ -328,12 +1062,27 @@ impl Client {
         let clan_tag = clan_tag.parse::<LogicLong>()?.to_string();
         let url = format!(
             "{}/clans/{}/capitalraidseasons",
-            Self::BASE_URL,
+            self.config.base_url,
             urlencoding::encode(&clan_tag)
         );
         self.parse_json(self.get(url), false).await
     }
 
+    /// Fetches every clan at once, with at most [`ClientBuilder::bulk_concurrency`] requests in
+    /// flight. Each tag is mapped to its own result, so one bad tag doesn't fail the others.
+    pub async fn get_clans_by_tags<S: AsRef<str> + Sync>(
+        &self,
+        clan_tags: &[S],
+    ) -> HashMap<String, Result<clan::Clan, APIError>> {
+        #[cfg(feature = "tracing")]
+        tracing::trace!("get_clans_by_tags({} tags)", clan_tags.len());
+        futures::stream::iter(clan_tags)
+            .map(|tag| async move { (tag.as_ref().to_string(), self.get_clan(tag.as_ref()).await) })
+            .buffer_unordered(self.bulk_concurrency())
+            .collect()
+            .await
+    }
+
     //         ╭──────────────────────────────────────────────────────────╮
     //         │                      Player Methods                      │
     //         ╰──────────────────────────────────────────────────────────╯
@@ -345,10 +1094,25 @@ impl Client {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_player({})", player_tag);
         let player_tag = player_tag.parse::<LogicLong>()?.to_string();
-        let url = format!("{}/players/{}", Self::BASE_URL, urlencoding::encode(&player_tag));
+        let url = format!("{}/players/{}", self.config.base_url, urlencoding::encode(&player_tag));
         self.parse_json(self.get(url), false).await
     }
 
+    /// Fetches every player at once, with at most [`ClientBuilder::bulk_concurrency`] requests in
+    /// flight. Each tag is mapped to its own result, so one bad tag doesn't fail the others.
+    pub async fn get_players<S: AsRef<str> + Sync>(
+        &self,
+        player_tags: &[S],
+    ) -> HashMap<String, Result<player::Player, APIError>> {
+        #[cfg(feature = "tracing")]
+        tracing::trace!("get_players({} tags)", player_tags.len());
+        futures::stream::iter(player_tags)
+            .map(|tag| async move { (tag.as_ref().to_string(), self.get_player(tag.as_ref()).await) })
+            .buffer_unordered(self.bulk_concurrency())
+            .collect()
+            .await
+    }
+
     /// # Errors
     ///
     /// This function will return an error if the request fails
@@ -360,8 +1124,11 @@ impl Client {
         #[cfg(feature = "tracing")]
         tracing::trace!("verify_player_token({}, {})", player_tag, token);
         let player_tag = player_tag.parse::<LogicLong>()?.to_string();
-        let url =
-            format!("{}/players/{}/verifytoken", Self::BASE_URL, urlencoding::encode(&player_tag));
+        let url = format!(
+            "{}/players/{}/verifytoken",
+            self.config.base_url,
+            urlencoding::encode(&player_tag)
+        );
         let token = format!("{{\"token\":\"{token}\"}}");
         self.parse_json(self.post(url, token), false).await
     }
@@ -376,7 +1143,7 @@ impl Client {
     pub async fn get_leagues(&self) -> Result<APIResponse<leagues::League>, APIError> {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_leagues()");
-        let url = format!("{}/leagues", Self::BASE_URL);
+        let url = format!("{}/leagues", self.config.base_url);
         self.parse_json(self.get(url), false).await
     }
 
@@ -398,7 +1165,7 @@ impl Client {
             ));
         }
         let mut url =
-            format!("{}/leagues/{}/seasons/{season_id}", Self::BASE_URL, league_id as i32);
+            format!("{}/leagues/{}/seasons/{season_id}", self.config.base_url, league_id as i32);
         if paging.is_some() {
             url = Url::parse_with_params(&url, paging.to_vec())?.to_string();
         }
@@ -414,7 +1181,7 @@ impl Client {
     ) -> Result<leagues::League, APIError> {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_league({})", league_id);
-        let url = format!("{}/leagues/{}", Self::BASE_URL, league_id as i32);
+        let url = format!("{}/leagues/{}", self.config.base_url, league_id as i32);
         self.parse_json(self.get(url), false).await
     }
 
@@ -433,7 +1200,7 @@ impl Client {
                     .to_string(),
             ));
         }
-        let url = format!("{}/leagues/{}/seasons", Self::BASE_URL, league_id as i32);
+        let url = format!("{}/leagues/{}/seasons", self.config.base_url, league_id as i32);
         self.parse_json(self.get(url), false).await
     }
 
@@ -446,7 +1213,7 @@ impl Client {
     ) -> Result<leagues::WarLeague, APIError> {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_war_league({})", war_league);
-        let url = format!("{}/warleagues/{}", Self::BASE_URL, war_league as i32);
+        let url = format!("{}/warleagues/{}", self.config.base_url, war_league as i32);
         self.parse_json(self.get(url), false).await
     }
 
@@ -456,7 +1223,7 @@ impl Client {
     pub async fn get_war_leagues(&self) -> Result<APIResponse<leagues::WarLeague>, APIError> {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_war_leagues()");
-        let url = format!("{}/warleagues", Self::BASE_URL);
+        let url = format!("{}/warleagues", self.config.base_url);
         self.parse_json(self.get(url), false).await
     }
 
@@ -473,7 +1240,7 @@ impl Client {
     ) -> Result<APIResponse<rankings::ClanRanking>, APIError> {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_clan_rankings({})", location);
-        let url = format!("{}/locations/{}/rankings/clans", Self::BASE_URL, location as i32);
+        let url = format!("{}/locations/{}/rankings/clans", self.config.base_url, location as i32);
         self.parse_json(self.get(url), false).await
     }
 
@@ -486,7 +1253,8 @@ impl Client {
     ) -> Result<APIResponse<rankings::PlayerRanking>, APIError> {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_player_rankings({})", location);
-        let url = format!("{}/locations/{}/rankings/players", Self::BASE_URL, location as i32);
+        let url =
+            format!("{}/locations/{}/rankings/players", self.config.base_url, location as i32);
         self.parse_json(self.get(url), false).await
     }
 
@@ -499,7 +1267,8 @@ impl Client {
     ) -> Result<APIResponse<rankings::ClanRanking>, APIError> {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_versus_clan_rankings({})", location);
-        let url = format!("{}/locations/{}/rankings/clans-versus", Self::BASE_URL, location as i32);
+        let url =
+            format!("{}/locations/{}/rankings/clans-versus", self.config.base_url, location as i32);
         self.parse_json(self.get(url), false).await
     }
 
@@ -512,8 +1281,10 @@ impl Client {
     ) -> Result<APIResponse<rankings::PlayerVersusRanking>, APIError> {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_versus_player_rankings({})", location);
-        let url =
-            format!("{}/locations/{}/rankings/players-versus", Self::BASE_URL, location as i32);
+        let url = format!(
+            "{}/locations/{}/rankings/players-versus",
+            self.config.base_url, location as i32
+        );
         self.parse_json(self.get(url), false).await
     }
 
@@ -523,7 +1294,7 @@ impl Client {
     pub async fn get_locations(&self) -> Result<APIResponse<location::Location>, APIError> {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_locations()");
-        let url = format!("{}/locations", Self::BASE_URL);
+        let url = format!("{}/locations", self.config.base_url);
         self.parse_json(self.get(url), false).await
     }
 
@@ -536,7 +1307,7 @@ impl Client {
     ) -> Result<location::Location, APIError> {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_location({})", location);
-        let url = format!("{}/locations/{}", Self::BASE_URL, location as i32);
+        let url = format!("{}/locations/{}", self.config.base_url, location as i32);
         self.parse_json(self.get(url), false).await
     }
 
@@ -550,7 +1321,7 @@ impl Client {
     pub async fn get_goldpass(&self) -> Result<gold_pass::GoldPass, APIError> {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_goldpass()");
-        let url = format!("{}/goldpass/seasons/current", Self::BASE_URL);
+        let url = format!("{}/goldpass/seasons/current", self.config.base_url);
         self.parse_json(self.get(url), false).await
     }
 
@@ -564,7 +1335,7 @@ impl Client {
     pub async fn get_player_labels(&self) -> Result<APIResponse<labels::PlayerLabel>, APIError> {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_player_labels()");
-        let url = format!("{}/labels/players", Self::BASE_URL);
+        let url = format!("{}/labels/players", self.config.base_url);
         self.parse_json(self.get(url), false).await
     }
 
@@ -574,135 +1345,431 @@ impl Client {
     pub async fn get_clan_labels(&self) -> Result<APIResponse<labels::ClanLabel>, APIError> {
         #[cfg(feature = "tracing")]
         tracing::trace!("get_clan_labels()");
-        let url = format!("{}/labels/clans", Self::BASE_URL);
+        let url = format!("{}/labels/clans", self.config.base_url);
         self.parse_json(self.get(url), false).await
     }
 
-    /// Runs the future that implements `Send` and parses the reqwest response into an
-    /// `APIResponse`.
-    ///
-    /// # Panics
-    ///
-    /// Panics if the JSON parsing fails for some odd reason. This is a bug and should be reported.
+    /// Sends the request and parses the response into an `APIResponse`.
     ///
     /// # Errors
     ///
-    /// This function will return an error if the request fails.
-    #[async_recursion]
-    pub(crate) async fn parse_json<T: DeserializeOwned>(
+    /// This function will return an error if the request fails, or [`APIError::Deserialize`] if
+    /// the response doesn't match `T`.
+    pub(crate) async fn parse_json<T>(
         &self,
         rb: Result<RequestBuilder, APIError>,
-        is_retry_and_not_cos: bool,
-    ) -> Result<T, APIError> {
-        match rb {
-            Ok(rb) => {
-                let cloned_rb = rb.try_clone();
-                match rb.send().await {
-                    Ok(resp) => {
-                        match resp.status() {
-                            reqwest::StatusCode::OK => {
-                                let text = resp.text().await?;
-                                Ok(serde_json::from_str(&text).unwrap_or_else(|e| panic!("Failure parsing json (please file a bug on the GitHub): {text}\nError: {e}")))
-                            }
-                            // 400
-                            reqwest::StatusCode::BAD_REQUEST => Err(APIError::BadParameters),
-                            // 403 - likely means the IP address has changed, let's reinit the
-                            // client then and try this again
-                            reqwest::StatusCode::FORBIDDEN => {
-                                if is_retry_and_not_cos {
-                                    #[cfg(feature = "tracing")]
-                                    tracing::debug!("403 Forbidden, but already retried, try checking your credentials?");
-                                    Err(APIError::AccessDenied)
-                                } else {
-                                    if let Err(e) = self.reinit().await {
-                                        return Err(APIError::LoginFailed(e.to_string()));
-                                    }
-                                    if let Some(rb) = cloned_rb {
-                                        self.parse_json(Ok(rb), true).await
-                                    } else {
-                                        Err(APIError::AccessDenied)
-                                    }
-                                }
-                            }
-                            // 404
-                            reqwest::StatusCode::NOT_FOUND => Err(APIError::NotFound),
-                            // 429
-                            reqwest::StatusCode::TOO_MANY_REQUESTS => {
-                                Err(APIError::RequestThrottled)
-                            }
-                            // 500
-                            reqwest::StatusCode::INTERNAL_SERVER_ERROR => {
-                                Err(APIError::UnknownError)
-                            }
-                            // 503
-                            reqwest::StatusCode::SERVICE_UNAVAILABLE => {
-                                Err(APIError::InMaintenance)
-                            }
-                            // edge cases
-                            _ => {
-                                let status = resp.status();
-                                #[cfg(feature = "tracing")]
-                                tracing::debug!("Unknown status code: {}", status);
-                                Err(APIError::BadResponse(resp.text().await?, status))
-                            }
-                        }
+        is_cos: bool,
+    ) -> Result<T, APIError>
+    where
+        T: DeserializeOwned,
+    {
+        let text = self.fetch(rb?, is_cos).await?;
+        let result =
+            serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(&text));
+        result.map_err(|e| {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(path = %e.path(), "Failure parsing json (please file a bug on the GitHub): {}", e);
+            APIError::Deserialize {
+                path: e.path().to_string(),
+                source: Arc::new(e.into_inner()),
+                body: text,
+            }
+        })
+    }
+
+    /// Returns the body of a successful response to the request, taken from the client's
+    /// [`CacheStore`] if it has a fresh copy.
+    ///
+    /// Concurrent GET requests for the same URL are coalesced, so only one of them is sent and
+    /// every caller shares its response.
+    async fn fetch(&self, rb: RequestBuilder, is_cos: bool) -> Result<String, APIError> {
+        let request = rb.build()?;
+        if is_cos || request.method() != reqwest::Method::GET {
+            return self.send_with_retries(request, is_cos).await.map(|response| response.body);
+        }
+
+        let key = request.url().to_string();
+        if let Some(cache) = &self.cache {
+            if let Some(body) = cache.get(&key).await {
+                #[cfg(feature = "tracing")]
+                tracing::trace!("cache hit for {}", key);
+                return Ok(body);
+            }
+        }
+
+        if !self.config.coalesce_requests {
+            return self.send_and_cache(request, key).await;
+        }
+
+        let flight = match self.in_flight.entry(key.clone()) {
+            Entry::Occupied(entry) => {
+                #[cfg(feature = "tracing")]
+                tracing::trace!("joining in-flight request for {}", key);
+                entry.get().clone()
+            }
+            Entry::Vacant(entry) => {
+                let client = self.clone();
+                let flight = async move {
+                    let result = client.send_and_cache(request, key.clone()).await;
+                    client.in_flight.remove(&key);
+                    result
+                }
+                .boxed()
+                .shared();
+                entry.insert(flight.clone());
+                flight
+            }
+        };
+        flight.await
+    }
+
+    /// Sends a GET request for the official API and caches the response if the client has a
+    /// [`CacheStore`].
+    async fn send_and_cache(
+        &self,
+        request: reqwest::Request,
+        key: String,
+    ) -> Result<String, APIError> {
+        let response = self.send_with_retries(request, false).await?;
+        if let Some(cache) = &self.cache {
+            if let Some(max_age) = response.max_age.filter(|max_age| !max_age.is_zero()) {
+                cache.insert(key, response.body.clone(), max_age).await;
+            }
+        }
+        Ok(response.body)
+    }
+
+    /// Sends the request through the client's [`HttpTransport`], authorizing it with the next key
+    /// unless it's meant for Clash of Stats, and returns the response if it was successful.
+    ///
+    /// Failed requests are re-sent with a fresh key according to the client's [`RetryPolicy`].
+    async fn send_with_retries(
+        &self,
+        request: reqwest::Request,
+        is_cos: bool,
+    ) -> Result<RawResponse, APIError> {
+        let mut attempt = 0;
+        let mut reinitialized = false;
+
+        loop {
+            // a body that can't be cloned can't be retried either
+            let Some(next) = request.try_clone() else {
+                let (request, _) = self.authorize(request, is_cos).await?;
+                return self.send(request).await?.error_for_status();
+            };
+
+            attempt += 1;
+            let generation = self.reinit_generation.load(Ordering::Acquire);
+            let (next, key) = self.authorize(next, is_cos).await?;
+            let sent_at = Instant::now();
+            let response = self.send(next).await;
+            let retry_after = response.as_ref().ok().and_then(|response| response.retry_after);
+            let result = response.and_then(RawResponse::error_for_status);
+            if let Some(key) = key {
+                self.record_outcome(key, &result, sent_at.elapsed(), retry_after);
+            }
+
+            match result {
+                // 403 - the IP address has changed, let's reinit the client then and try this
+                // again with a fresh key. Any other 403 (e.g. a private war log) is returned as is
+                Err(APIError::AccessDenied(body))
+                    if !is_cos && body.as_deref().is_some_and(ApiErrorBody::is_invalid_ip) =>
+                {
+                    if self.static_keys {
+                        #[cfg(feature = "tracing")]
+                        tracing::warn!("403 Forbidden with a pre-issued key, not reinitializing");
+                        return Err(APIError::StaticKeyRejected);
+                    }
+                    if reinitialized {
+                        #[cfg(feature = "tracing")]
+                        tracing::debug!(
+                            "403 Forbidden, but already retried, try checking your credentials?"
+                        );
+                        return Err(APIError::AccessDenied(body));
                     }
-                    Err(e) => Err(APIError::RequestFailed(e)),
+                    // unless the keys were already replaced while this request was in flight
+                    if self.reinit_generation.load(Ordering::Acquire) == generation {
+                        self.reinit().await?;
+                    }
+                    reinitialized = true;
+                    // the reinit isn't counted against the retry policy
+                    attempt -= 1;
+                }
+                Err(e) if self.config.retry_policy.should_retry(attempt, &e) => {
+                    let delay = self.config.retry_policy.delay(attempt, retry_after);
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("attempt {} failed ({}), retrying in {:?}", attempt, e, delay);
+                    tokio::time::sleep(delay).await;
                 }
+                result => return result,
             }
-            Err(e) => Err(e),
         }
     }
 
-    fn get_next_key(&self) -> String {
-        // increment key_token_index, unless it would be larger than the account's token size (10),
-        // then reset to 0 and increment key_account_index
+    /// The war log endpoints answer a clan's private war log with a plain `accessDenied`, which is
+    /// turned into [`APIError::PrivateWarLog`]. Any other 403, including one without the API's
+    /// error body, is returned as is.
+    fn private_war_log<T>(result: Result<T, APIError>) -> Result<T, APIError> {
+        match result {
+            Err(APIError::AccessDenied(Some(body)))
+                if body.reason == "accessDenied" && !body.is_invalid_key() =>
+            {
+                Err(APIError::PrivateWarLog)
+            }
+            result => result,
+        }
+    }
 
-        let mut account_index = self.account_index.load(Ordering::Relaxed);
-        let mut key_index = self.key_index.load(Ordering::Relaxed);
+    /// Waits until the accounts are logged in, for at most [`ClientBuilder::ready_timeout`]
+    async fn wait_until_ready(&self) -> Result<(), APIError> {
+        let mut ready = self.ready.subscribe();
+        let wait = async { ready.wait_for(|ready| *ready).await.is_ok() };
+        match tokio::time::timeout(self.config.ready_timeout, wait).await {
+            Ok(true) => Ok(()),
+            _ => Err(APIError::ClientNotReady),
+        }
+    }
 
-        let accounts = self.accounts.iter().collect::<Vec<_>>();
-        let size_of_keys = accounts[account_index].keys.len().min(10);
+    /// Sets the request's bearer token to the next key, unless it's meant for Clash of Stats.
+    async fn authorize(
+        &self,
+        mut request: reqwest::Request,
+        is_cos: bool,
+    ) -> Result<(reqwest::Request, Option<Secret>), APIError> {
+        if is_cos {
+            return Ok((request, None));
+        }
 
-        // if we're at the end of this account's keys..
-        if key_index == size_of_keys - 1 {
-            // reset token index anyways
-            key_index = 0;
-            // ..and at the end of the accounts
-            if account_index == (accounts.len() - 1) {
-                // then we've reached end of accounts, go back to first account
-                account_index = 0;
-            } else {
-                // otherwise, just increment account index
-                account_index += 1;
+        self.wait_until_ready().await?;
+        let key = self.acquire_key().await?;
+        let bearer = Zeroizing::new(format!("Bearer {}", key.expose_secret()));
+        let mut value = reqwest::header::HeaderValue::from_str(&bearer)?;
+        value.set_sensitive(true);
+        request.headers_mut().insert(reqwest::header::AUTHORIZATION, value);
+        Ok((request, Some(key)))
+    }
+
+    /// Records how the request sent with `key` went, and has the key replaced once it's been
+    /// quarantined
+    fn record_outcome(
+        &self,
+        key: Secret,
+        result: &Result<RawResponse, APIError>,
+        latency: Duration,
+        retry_after: Option<Duration>,
+    ) {
+        let outcome = match result {
+            Ok(_) | Err(APIError::BadParameters(_) | APIError::NotFound(_)) => Outcome::Success,
+            Err(APIError::RequestThrottled(_)) => {
+                Outcome::Throttled(retry_after.unwrap_or(self.config.key_cooldown))
             }
-        } else {
-            // otherwise, just increment token index
-            key_index += 1;
+            // a 403 because of the IP address is handled by logging in again instead
+            Err(APIError::AccessDenied(Some(body))) if body.is_invalid_key() => Outcome::Rejected,
+            // the request never made it, which says nothing about the key
+            Err(APIError::RequestFailed(_)) => return,
+            Err(_) => Outcome::Responded,
+        };
+
+        if self.key_ring.record(&key, outcome, latency, self.config.quarantine_after)
+            && !self.static_keys
+        {
+            #[cfg(feature = "tracing")]
+            tracing::warn!("quarantined a key after repeated 403s, replacing it");
+            let client = self.clone();
+            tokio::spawn(async move { client.replace_key(&key).await });
         }
+    }
 
-        let token = accounts
-            .get(account_index)
-            .unwrap_or_else(|| {
+    /// Revokes a quarantined key and creates a new one on its account
+    async fn replace_key(&self, key: &Secret) {
+        let account = self
+            .accounts
+            .iter()
+            .find(|account| account.keys.iter().any(|k| &k.key == key))
+            .map(|account| account.clone());
+        let Some(mut account) = account else {
+            return;
+        };
+
+        // accounts restored from a snapshot only log in once one of their keys is rejected
+        if account.portal.is_none() {
+            match account.re_login().await {
+                Ok(ip) => *self.ip_address.lock() = ip,
                 #[cfg(feature = "tracing")]
-                tracing::warn!("No account found at index {account_index}");
-                panic!("No account found at index {account_index}")
-            })
-            .keys
-            .keys
-            .get(key_index)
-            .unwrap_or_else(|| {
+                Err(e) => return tracing::warn!("failed to login to replace a key: {:#}", e),
+                #[cfg(not(feature = "tracing"))]
+                Err(_) => return,
+            }
+        }
+
+        let cidrs = self.config.egress_cidrs(&self.ip_address.lock());
+        let result = account.replace_key(key, &cidrs).await;
+        // even if it failed, a revoked key shouldn't stay in the rotation
+        self.accounts.insert(account.credential.clone(), account);
+        self.rebuild_key_ring();
+        match result {
+            Ok(()) => {}
+            #[cfg(feature = "tracing")]
+            Err(e) => tracing::warn!("failed to replace a quarantined key: {:#}", e),
+            #[cfg(not(feature = "tracing"))]
+            Err(_) => {}
+        }
+    }
+
+    /// Sends the request as-is and reads the response.
+    async fn send(&self, request: reqwest::Request) -> Result<RawResponse, APIError> {
+        let resp = self.transport.execute(request).await?;
+        let status = resp.status();
+        let header = |name| resp.headers().get(name).and_then(|value| value.to_str().ok());
+        let retry_after = header(reqwest::header::RETRY_AFTER).and_then(parse_retry_after);
+        let max_age = header(reqwest::header::CACHE_CONTROL).and_then(parse_max_age);
+        let body = resp.text().await?;
+        Ok(RawResponse { status, retry_after, max_age, body })
+    }
+
+    /// Returns the next key in the rotation. With a rate limit configured, keys that have
+    /// exhausted their budget are skipped, and if every key has, this waits until one refills.
+    async fn acquire_key(&self) -> Result<Secret, APIError> {
+        let Some(requests_per_second) = self.config.requests_per_second else {
+            return self.get_next_key().await;
+        };
+
+        loop {
+            let mut wait = Duration::MAX;
+            for _ in 0..self.key_count().max(1) {
+                let key = match self.key_ring.next() {
+                    Ok(key) => key,
+                    Err(Some(cooldown)) => {
+                        wait = wait.min(cooldown);
+                        break;
+                    }
+                    Err(None) => return Err(APIError::NoKeysAvailable),
+                };
+                let bucket = self
+                    .rate_limiters
+                    .entry(key.clone())
+                    .or_insert_with(|| TokenBucket::new(requests_per_second));
+                match bucket.try_acquire() {
+                    Ok(()) => return Ok(key),
+                    Err(refill) => wait = wait.min(refill),
+                }
+            }
+
+            #[cfg(feature = "tracing")]
+            tracing::trace!("every key is rate limited, waiting {:?}", wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// How many requests bulk methods may have in flight, one per key unless configured
+    fn bulk_concurrency(&self) -> usize {
+        self.config.bulk_concurrency.unwrap_or_else(|| self.key_count()).max(1)
+    }
+
+    /// The number of keys in the rotation
+    fn key_count(&self) -> usize {
+        self.key_ring.len()
+    }
+
+    /// The next key that isn't cooling down or quarantined, waiting for a cooldown to run out if
+    /// every key is
+    async fn get_next_key(&self) -> Result<Secret, APIError> {
+        loop {
+            match self.key_ring.next() {
+                Ok(key) => return Ok(key),
+                Err(Some(cooldown)) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::trace!("every key is cooling down, waiting {:?}", cooldown);
+                    tokio::time::sleep(cooldown).await;
+                }
+                Err(None) => return Err(APIError::NoKeysAvailable),
+            }
+        }
+    }
+}
+
+/// A response that has been read, but not yet mapped to a result
+#[derive(Debug)]
+struct RawResponse {
+    status: reqwest::StatusCode,
+    retry_after: Option<Duration>,
+    max_age: Option<Duration>,
+    body: String,
+}
+
+impl RawResponse {
+    /// Maps the response's status code to an error, unless it was successful. The API's error
+    /// body is attached to the error if there is one.
+    fn error_for_status(self) -> Result<Self, APIError> {
+        if self.status == reqwest::StatusCode::OK {
+            return Ok(self);
+        }
+
+        let body = ApiErrorBody::parse(&self.body);
+        match self.status {
+            // 400
+            reqwest::StatusCode::BAD_REQUEST => Err(APIError::BadParameters(body)),
+            // 403
+            reqwest::StatusCode::FORBIDDEN => match body {
+                Some(body) if body.reason == "privateWarLog" => Err(APIError::PrivateWarLog),
+                body => Err(APIError::AccessDenied(body)),
+            },
+            // 404
+            reqwest::StatusCode::NOT_FOUND => Err(APIError::NotFound(body)),
+            // 429
+            reqwest::StatusCode::TOO_MANY_REQUESTS => Err(APIError::RequestThrottled(body)),
+            // 500
+            reqwest::StatusCode::INTERNAL_SERVER_ERROR => Err(APIError::UnknownError),
+            // 503
+            reqwest::StatusCode::SERVICE_UNAVAILABLE => Err(APIError::InMaintenance(body)),
+            // edge cases
+            status => {
                 #[cfg(feature = "tracing")]
-                tracing::warn!("No key found at index {key_index}");
-                panic!("No key found at index {key_index}");
-            })
-            .clone();
+                tracing::debug!("Unknown status code: {}", status);
+                Err(APIError::BadResponse(self.body, status, body))
+            }
+        }
+    }
+}
+
+/// Why a re-login failed, failing to manage the keys once logged in counts as the portal being
+/// unavailable
+fn login_error(error: &anyhow::Error) -> APIError {
+    APIError::LoginFailed(login_reason(error))
+}
+
+fn login_reason(error: &anyhow::Error) -> LoginError {
+    match error.downcast_ref::<APIError>() {
+        Some(APIError::LoginFailed(reason)) => reason.clone(),
+        _ => LoginError::PortalUnavailable(format!("{error:#}")),
+    }
+}
+
+/// The report entry of an account that failed to log in
+fn login_failure(email: String, error: &anyhow::Error) -> LoginFailure {
+    LoginFailure { email, reason: login_reason(error), error: format!("{error:#}") }
+}
 
-        self.account_index.store(account_index, Ordering::Relaxed);
-        self.key_index.store(key_index, Ordering::Relaxed);
+/// Parses a `Retry-After` header, given either in seconds or as an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().ok()
+}
 
-        token.key
+/// Parses the `max-age` directive of a `Cache-Control` header, unless storing is forbidden
+fn parse_max_age(value: &str) -> Option<Duration> {
+    let directives = value.split(',').map(str::trim).collect::<Vec<_>>();
+    if directives.iter().any(|directive| directive.eq_ignore_ascii_case("no-store")) {
+        return None;
     }
+    directives
+        .iter()
+        .find_map(|directive| directive.strip_prefix("max-age="))
+        .and_then(|seconds| seconds.trim_matches('"').parse().ok())
+        .map(Duration::from_secs)
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -0,0 +1,75 @@
+use std::{
+    num::NonZeroUsize,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use lru::LruCache;
+use parking_lot::Mutex;
+
+/// Stores response bodies of the official API by URL, for as long as the API's `Cache-Control`
+/// header allows.
+///
+/// Implement this over a shared store (e.g. Redis) to let several services share one cache.
+#[async_trait]
+pub trait CacheStore: std::fmt::Debug + Send + Sync {
+    /// Returns the cached body for `key`, unless it's missing or has expired
+    async fn get(&self, key: &str) -> Option<String>;
+
+    /// Caches `value` under `key` for `ttl`
+    async fn insert(&self, key: String, value: String, ttl: Duration);
+}
+
+#[async_trait]
+impl<T: CacheStore + ?Sized> CacheStore for Arc<T> {
+    async fn get(&self, key: &str) -> Option<String> {
+        (**self).get(key).await
+    }
+
+    async fn insert(&self, key: String, value: String, ttl: Duration) {
+        (**self).insert(key, value, ttl).await;
+    }
+}
+
+/// An in-memory [`CacheStore`] that evicts the least recently used entry once it's full
+#[derive(Debug)]
+pub struct MemoryCache {
+    entries: Mutex<LruCache<String, (String, Instant)>>,
+}
+
+impl MemoryCache {
+    const DEFAULT_CAPACITY: usize = 1024;
+
+    /// Returns a cache holding at most `capacity` responses
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        Self { entries: Mutex::new(LruCache::new(capacity)) }
+    }
+}
+
+impl Default for MemoryCache {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+#[async_trait]
+impl CacheStore for MemoryCache {
+    async fn get(&self, key: &str) -> Option<String> {
+        let mut entries = self.entries.lock();
+        match entries.get(key) {
+            Some((value, expires_at)) if *expires_at > Instant::now() => Some(value.clone()),
+            Some(_) => {
+                entries.pop(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    async fn insert(&self, key: String, value: String, ttl: Duration) {
+        self.entries.lock().put(key, (value, Instant::now() + ttl));
+    }
+}
@@ -7,7 +7,7 @@ pub mod cos {
     use crate::{api::Client, credentials::Credentials, error::APIError};
 
     impl Client {
-        const BASE_COS_URL: &'static str = "https://api.clashofstats.com";
+        pub(crate) const BASE_COS_URL: &'static str = "https://api.clashofstats.com";
         const COS_LOGIN_ENDPOINT: &'static str = "/login";
 
         const COS_PLAYERS_ENDPOINT: &'static str = "/players";
@@ -77,9 +77,9 @@ pub mod cos {
                 success: bool,
             }
 
-            let url = format!("{}{}", Self::BASE_COS_URL, Self::COS_LOGIN_ENDPOINT);
+            let url = format!("{}{}", self.config.cos_base_url, Self::COS_LOGIN_ENDPOINT);
             let body =
-                serde_json::to_string(credentials.0.get(0).expect("Your credentials are empty"))
+                serde_json::to_string(credentials.0.first().expect("Your credentials are empty"))
                     .unwrap();
 
             let _login: LoginResponse = self.parse_json(self.cos_post(url, body), true).await?;
@@ -98,7 +98,7 @@ pub mod cos {
             player_tag.parse::<LogicLong>()?.to_string();
             let url = format!(
                 "{}{}/{}",
-                Self::BASE_COS_URL,
+                self.config.cos_base_url,
                 Self::COS_PLAYERS_ENDPOINT,
                 urlencoding::encode(player_tag)
             );
@@ -114,7 +114,7 @@ pub mod cos {
             player_tag.parse::<LogicLong>()?;
             let url = format!(
                 "{}{}/{}{}",
-                Self::BASE_COS_URL,
+                self.config.cos_base_url,
                 Self::COS_PLAYERS_ENDPOINT,
                 urlencoding::encode(player_tag),
                 Self::COS_PLAYERS_HISTORY_ENDPOINT
@@ -128,7 +128,7 @@ pub mod cos {
             clan_tag.parse::<LogicLong>()?;
             let url = format!(
                 "{}{}/{}",
-                Self::BASE_COS_URL,
+                self.config.cos_base_url,
                 Self::COS_CLANS_ENDPOINT,
                 urlencoding::encode(clan_tag)
             );
@@ -144,7 +144,7 @@ pub mod cos {
             clan_tag.parse::<LogicLong>()?;
             let url = format!(
                 "{}{}/{}{}",
-                Self::BASE_COS_URL,
+                self.config.cos_base_url,
                 Self::COS_CLANS_ENDPOINT,
                 urlencoding::encode(clan_tag),
                 Self::COS_CLANS_PAST_MEMBERS_ENDPOINT
@@ -159,7 +159,11 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_war_wins_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_CLAN_WAR_WINS_ENDPOINT,),
+                &format!(
+                    "{}{}",
+                    self.config.cos_base_url,
+                    Self::COS_RANKINGS_CLAN_WAR_WINS_ENDPOINT,
+                ),
                 options.build_for_clan(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -174,7 +178,7 @@ pub mod cos {
             let url = Url::parse_with_params(
                 &format!(
                     "{}{}",
-                    Self::BASE_COS_URL,
+                    self.config.cos_base_url,
                     Self::COS_RANKINGS_CLAN_WAR_WIN_STREAK_ENDPOINT,
                 ),
                 options.build_for_clan(),
@@ -191,7 +195,7 @@ pub mod cos {
             let url = Url::parse_with_params(
                 &format!(
                     "{}{}",
-                    Self::BASE_COS_URL,
+                    self.config.cos_base_url,
                     Self::COS_RANKINGS_CLAN_BEST_WAR_WIN_STREAK_ENDPOINT,
                 ),
                 options.build_for_clan(),
@@ -206,7 +210,11 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_clan_trophies_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_CLAN_TROPHIES_ENDPOINT,),
+                &format!(
+                    "{}{}",
+                    self.config.cos_base_url,
+                    Self::COS_RANKINGS_CLAN_TROPHIES_ENDPOINT,
+                ),
                 options.build_for_clan(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -221,7 +229,7 @@ pub mod cos {
             let url = Url::parse_with_params(
                 &format!(
                     "{}{}",
-                    Self::BASE_COS_URL,
+                    self.config.cos_base_url,
                     Self::COS_RANKINGS_CLAN_VERSUS_TROPHIES_ENDPOINT,
                 ),
                 options.build_for_clan(),
@@ -236,7 +244,11 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_trophies_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_TROPHIES_ENDPOINT,),
+                &format!(
+                    "{}{}",
+                    self.config.cos_base_url,
+                    Self::COS_RANKINGS_PLAYER_TROPHIES_ENDPOINT,
+                ),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -251,7 +263,7 @@ pub mod cos {
             let url = Url::parse_with_params(
                 &format!(
                     "{}{}",
-                    Self::BASE_COS_URL,
+                    self.config.cos_base_url,
                     Self::COS_RANKINGS_PLAYER_VERSUS_TROPHIES_ENDPOINT,
                 ),
                 options.build_for_player(),
@@ -268,7 +280,7 @@ pub mod cos {
             let url = Url::parse_with_params(
                 &format!(
                     "{}{}",
-                    Self::BASE_COS_URL,
+                    self.config.cos_base_url,
                     Self::COS_RANKINGS_PLAYER_BEST_TROPHIES_ENDPOINT,
                 ),
                 options.build_for_player(),
@@ -285,7 +297,7 @@ pub mod cos {
             let url = Url::parse_with_params(
                 &format!(
                     "{}{}",
-                    Self::BASE_COS_URL,
+                    self.config.cos_base_url,
                     Self::COS_RANKINGS_PLAYER_BEST_VERSUS_TROPHIES_ENDPOINT,
                 ),
                 options.build_for_player(),
@@ -300,7 +312,11 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_legend_trophies_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_LEGEND_TROPHIES,),
+                &format!(
+                    "{}{}",
+                    self.config.cos_base_url,
+                    Self::COS_RANKINGS_PLAYER_LEGEND_TROPHIES,
+                ),
                 options.build_for_legends(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -313,7 +329,7 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_war_stars_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_WAR_STARS,),
+                &format!("{}{}", self.config.cos_base_url, Self::COS_RANKINGS_PLAYER_WAR_STARS,),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -326,7 +342,7 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_cwl_war_stars_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_CWL_WAR_STARS,),
+                &format!("{}{}", self.config.cos_base_url, Self::COS_RANKINGS_PLAYER_CWL_WAR_STARS,),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -339,7 +355,7 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_attack_wins_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_ATTACK_WINS,),
+                &format!("{}{}", self.config.cos_base_url, Self::COS_RANKINGS_PLAYER_ATTACK_WINS,),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -352,7 +368,7 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_defense_wins_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_DEFENSE_WINS,),
+                &format!("{}{}", self.config.cos_base_url, Self::COS_RANKINGS_PLAYER_DEFENSE_WINS,),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -365,7 +381,11 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_versus_battle_wins_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_VERSUS_BATTLE_WINS,),
+                &format!(
+                    "{}{}",
+                    self.config.cos_base_url,
+                    Self::COS_RANKINGS_PLAYER_VERSUS_BATTLE_WINS,
+                ),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -378,7 +398,7 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_heroic_heist_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_HEROIC_HEIST,),
+                &format!("{}{}", self.config.cos_base_url, Self::COS_RANKINGS_PLAYER_HEROIC_HEIST,),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -391,7 +411,7 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_conqueror_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_CONQUEROR,),
+                &format!("{}{}", self.config.cos_base_url, Self::COS_RANKINGS_PLAYER_CONQUEROR,),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -404,7 +424,7 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_unbreakable_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_UNBREAKABLE,),
+                &format!("{}{}", self.config.cos_base_url, Self::COS_RANKINGS_PLAYER_UNBREAKABLE,),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -417,7 +437,7 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_humiliator_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_HUMILIATOR,),
+                &format!("{}{}", self.config.cos_base_url, Self::COS_RANKINGS_PLAYER_HUMILIATOR,),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -430,7 +450,7 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_un_build_it_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_UN_BUILD_IT,),
+                &format!("{}{}", self.config.cos_base_url, Self::COS_RANKINGS_PLAYER_UN_BUILD_IT,),
                 options.build_for_builder(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -443,7 +463,11 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_games_champion_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_GAMES_CHAMPION,),
+                &format!(
+                    "{}{}",
+                    self.config.cos_base_url,
+                    Self::COS_RANKINGS_PLAYER_GAMES_CHAMPION,
+                ),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -456,7 +480,11 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_troops_donated_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_TROOPS_DONATED,),
+                &format!(
+                    "{}{}",
+                    self.config.cos_base_url,
+                    Self::COS_RANKINGS_PLAYER_TROOPS_DONATED,
+                ),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -469,7 +497,11 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_troops_received_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_TROOPS_RECEIVED,),
+                &format!(
+                    "{}{}",
+                    self.config.cos_base_url,
+                    Self::COS_RANKINGS_PLAYER_TROOPS_RECEIVED,
+                ),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -482,7 +514,11 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_friend_in_need_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_FRIEND_IN_NEED,),
+                &format!(
+                    "{}{}",
+                    self.config.cos_base_url,
+                    Self::COS_RANKINGS_PLAYER_FRIEND_IN_NEED,
+                ),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -495,7 +531,7 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_exp_level_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_EXP_LEVEL,),
+                &format!("{}{}", self.config.cos_base_url, Self::COS_RANKINGS_PLAYER_EXP_LEVEL,),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -508,7 +544,7 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_well_seasoned_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_WELL_SEASONED,),
+                &format!("{}{}", self.config.cos_base_url, Self::COS_RANKINGS_PLAYER_WELL_SEASONED,),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -521,7 +557,11 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_get_those_goblins_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_GET_THOSE_GOBLINS,),
+                &format!(
+                    "{}{}",
+                    self.config.cos_base_url,
+                    Self::COS_RANKINGS_PLAYER_GET_THOSE_GOBLINS,
+                ),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -534,7 +574,7 @@ pub mod cos {
             #[cfg(feature = "tracing")]
             tracing::trace!("cos_get_player_nice_and_tidy_leaderboard({})", options);
             let url = Url::parse_with_params(
-                &format!("{}{}", Self::BASE_COS_URL, Self::COS_RANKINGS_PLAYER_NICE_AND_TIDY,),
+                &format!("{}{}", self.config.cos_base_url, Self::COS_RANKINGS_PLAYER_NICE_AND_TIDY,),
                 options.build_for_player(),
             )?;
             self.parse_json(self.cos_get(url), true).await
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::secret::Secret;
+
 #[derive(Debug, Default)]
 pub struct CredentialsBuilder {
     pub credentials: Credentials,
@@ -11,7 +13,7 @@ pub struct Credentials(pub Vec<Credential>);
 #[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Credential {
     email: String,
-    password: String,
+    password: Secret,
 }
 
 impl CredentialsBuilder {
@@ -21,7 +23,7 @@ impl CredentialsBuilder {
 
     #[must_use]
     pub fn add_credential(mut self, email: String, password: String) -> Self {
-        self.credentials.0.push(Credential { email, password });
+        self.credentials.0.push(Credential::new(email, password));
         self
     }
 
@@ -32,13 +34,19 @@ impl CredentialsBuilder {
 }
 
 impl Credential {
+    #[must_use]
+    pub const fn new(email: String, password: String) -> Self {
+        Self { email, password: Secret::new(password) }
+    }
+
     #[must_use]
     pub fn email(&self) -> &str {
         &self.email
     }
 
+    /// The password, redacted when printed, see [`Secret::expose_secret`]
     #[must_use]
-    pub fn password(&self) -> &str {
+    pub const fn password(&self) -> &Secret {
         &self.password
     }
 }
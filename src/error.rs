@@ -1,47 +1,167 @@
+use std::sync::Arc;
+
 use reqwest::header::InvalidHeaderValue;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-#[derive(Debug, Error)]
+/// The JSON body the API sends along with an error status code, e.g.
+/// `{"reason":"accessDenied.invalidIp","message":"Invalid authorization: API key does not allow access from IP 1.2.3.4"}`
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ApiErrorBody {
+    /// A machine-readable reason such as `notFound`, `accessDenied.invalidIp` or `inMaintenance`
+    pub reason: String,
+    pub message: Option<String>,
+    #[serde(rename = "type")]
+    pub type_: Option<String>,
+    pub detail: Option<serde_json::Value>,
+}
+
+impl ApiErrorBody {
+    /// Parses an error body, returning `None` if it isn't the API's JSON error format
+    #[must_use]
+    pub fn parse(body: &str) -> Option<Box<Self>> {
+        serde_json::from_str(body).ok()
+    }
+
+    /// Whether the key was rejected because it wasn't issued for the IP address it's used from
+    #[must_use]
+    pub fn is_invalid_ip(&self) -> bool {
+        self.reason == "accessDenied.invalidIp"
+    }
+
+    /// Whether the key itself was rejected, e.g. because it was revoked
+    #[must_use]
+    pub fn is_invalid_key(&self) -> bool {
+        self.reason == "accessDenied"
+            && self.message.as_deref().is_some_and(|m| m.starts_with("Invalid authorization"))
+    }
+}
+
+impl std::fmt::Display for ApiErrorBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "{}: {}", self.reason, message),
+            None => write!(f, "{}", self.reason),
+        }
+    }
+}
+
+/// The credentials that failed to log in when a [`Client`](crate::api::Client) was built or last
+/// logged in again
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoginReport {
+    pub failures: Vec<LoginFailure>,
+}
+
+/// Why the account with this email failed to log in, `error` is the message with its context
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoginFailure {
+    pub email: String,
+    pub reason: LoginError,
+    pub error: String,
+}
+
+impl LoginReport {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl std::fmt::Display for LoginReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.failures.is_empty() {
+            return write!(f, "no credentials were given");
+        }
+        let failures = self
+            .failures
+            .iter()
+            .map(|failure| format!("{}: {}", failure.email, failure.error))
+            .collect::<Vec<_>>();
+        write!(f, "{}", failures.join(", "))
+    }
+}
+
+/// Why logging into the developer portal failed
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum LoginError {
+    /// The portal refused the email or password
+    #[error("invalid credentials")]
+    InvalidCredentials,
+    /// The portal couldn't be reached or answered with an error
+    #[error("developer portal unavailable: {0}")]
+    PortalUnavailable(String),
+    /// The portal's response, such as the claims of its temporary API token, isn't in the
+    /// expected format
+    #[error("unexpected response from the developer portal: {0}")]
+    UnexpectedResponse(String),
+}
+
+/// Formats an optional error body as a suffix to an error message
+fn describe(body: &Option<Box<ApiErrorBody>>) -> String {
+    body.as_ref().map_or_else(String::new, |body| format!(" ({body})"))
+}
+
+/// Errors are cheap to clone so that requests coalesced into one can all share its outcome.
+#[derive(Clone, Debug, Error)]
 pub enum APIError {
-    /// API hasn't been initialized yet (logging in + making keys).
-    #[error("Client hasn't been initialized yet (logging in + making keys).")]
+    /// API wasn't initialized (logging in + making keys) within the client's ready timeout.
+    #[error("Client wasn't initialized in time (logging in + making keys).")]
     ClientNotReady,
     /// Failed to query the current ip address.
     #[error("Failed to get IP address: {0}")]
     FailedGetIP(String),
-    /// Failed to login to an account, either due to invalid credentials or a server error.
+    /// Failed to login to an account, the reason says whether the credentials, the portal or its
+    /// response is to blame.
     #[error("Failed to login: {0}")]
-    LoginFailed(String),
+    LoginFailed(LoginError),
+    /// None of the credentials could log in, see the report for why each of them failed.
+    #[error("Every account failed to login: {0}")]
+    NoAccountsLoggedIn(LoginReport),
+    /// None of the accounts have a key to send the request with.
+    #[error("No API keys are available")]
+    NoKeysAvailable,
     /// Reqwest error
     #[error("Request failed: {0}")]
-    RequestFailed(reqwest::Error),
+    RequestFailed(Arc<reqwest::Error>),
     /// Invalid Header, should never happen
     #[error("Invalid header: {0}")]
-    InvalidHeader(InvalidHeaderValue),
+    InvalidHeader(Arc<InvalidHeaderValue>),
     /// Failed to parse a URL, should never happen
     #[error("Failed to parse URL: {0}")]
     BadUrl(url::ParseError),
     /// Status code of 400
-    #[error("Bad parameters")]
-    BadParameters,
+    #[error("Bad parameters{}", describe(.0))]
+    BadParameters(Option<Box<ApiErrorBody>>),
     /// Status code of 403
-    #[error("Access denied")]
-    AccessDenied,
+    #[error("Access denied{}", describe(.0))]
+    AccessDenied(Option<Box<ApiErrorBody>>),
+    /// Status code of 403 when requesting the war log or current war of a clan with a private war log
+    #[error("The clan's war log is private")]
+    PrivateWarLog,
+    /// Status code of 403 with a key passed to [`Client::with_tokens`](crate::api::Client::with_tokens),
+    /// which can't be recreated
+    #[error("API key was rejected, ensure it was issued for this IP address")]
+    StaticKeyRejected,
     /// Status code of 404
-    #[error("Not found")]
-    NotFound,
+    #[error("Not found{}", describe(.0))]
+    NotFound(Option<Box<ApiErrorBody>>),
     /// Status code of 429
-    #[error("Request Throttled")]
-    RequestThrottled,
+    #[error("Request Throttled{}", describe(.0))]
+    RequestThrottled(Option<Box<ApiErrorBody>>),
     /// Status code of 500
     #[error("Unknown error (500)")]
     UnknownError,
     /// Status code of 503
-    #[error("In maintenance")]
-    InMaintenance,
+    #[error("In maintenance{}", describe(.0))]
+    InMaintenance(Option<Box<ApiErrorBody>>),
+    /// The response didn't match its model, likely because the API added or changed a field.
+    /// `path` points to the offending field, e.g. `memberList[3].league`.
+    #[error("Failed to deserialize the response at `{path}`: {source}")]
+    Deserialize { body: String, path: String, source: Arc<serde_json::Error> },
     /// All other cases (edge cases/unknown status codes)
     #[error("Bad response: {0}")]
-    BadResponse(String, reqwest::StatusCode),
+    BadResponse(String, reqwest::StatusCode, Option<Box<ApiErrorBody>>),
     /// From malformed cursors or using invalid leagues
     #[error("Invalid parameters: {0}")]
     InvalidParameters(String),
@@ -54,7 +174,7 @@ pub enum APIError {
 
 impl From<reqwest::Error> for APIError {
     fn from(e: reqwest::Error) -> Self {
-        Self::RequestFailed(e)
+        Self::RequestFailed(Arc::new(e))
     }
 }
 
@@ -66,6 +186,6 @@ impl From<url::ParseError> for APIError {
 
 impl From<InvalidHeaderValue> for APIError {
     fn from(e: InvalidHeaderValue) -> Self {
-        Self::InvalidHeader(e)
+        Self::InvalidHeader(Arc::new(e))
     }
 }
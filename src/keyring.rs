@@ -0,0 +1,207 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use arc_swap::ArcSwap;
+use dashmap::DashMap;
+
+use crate::secret::Secret;
+
+/// A key in the rotation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct RingKey {
+    /// The key's id on the developer portal, or `static-<index>` for a pre-issued key
+    pub(crate) id: String,
+    /// Email of the account the key belongs to
+    pub(crate) account: Option<String>,
+    pub(crate) token: Secret,
+}
+
+/// How a request sent with a key turned out, as far as the key is concerned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    /// The API answered, whatever the answer was
+    Success,
+    /// 429, the key rests for this long
+    Throttled(Duration),
+    /// 403, the key itself was refused
+    Rejected,
+    /// The API answered with an error that isn't the key's fault, e.g. maintenance
+    Responded,
+}
+
+/// Whether a key is handed out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyStatus {
+    Healthy,
+    /// Throttled recently, skipped until the cooldown runs out
+    CoolingDown(Duration),
+    /// Refused too many times in a row, skipped until it's replaced
+    Quarantined,
+}
+
+/// How the requests sent with a key went, see [`Client::key_stats`](crate::api::Client::key_stats)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyStats {
+    /// The key's id on the developer portal, or `static-<index>` for a key passed to
+    /// [`Client::with_tokens`](crate::api::Client::with_tokens)
+    pub id: String,
+    /// Email of the account the key belongs to
+    pub account: Option<String>,
+    pub status: KeyStatus,
+    pub successes: u64,
+    /// Responses with a status code of 429
+    pub throttled: u64,
+    /// Responses with a status code of 403 that refused the key itself
+    pub rejected: u64,
+    /// Average time until the API answered, `None` until it has
+    pub average_latency: Option<Duration>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct KeyHealth {
+    successes: u64,
+    throttled: u64,
+    rejected: u64,
+    consecutive_rejections: u32,
+    responses: u32,
+    total_latency: Duration,
+    cooldown_until: Option<Instant>,
+    quarantined: bool,
+}
+
+impl KeyHealth {
+    fn status(&self, now: Instant) -> KeyStatus {
+        if self.quarantined {
+            return KeyStatus::Quarantined;
+        }
+        match self.cooldown_until {
+            Some(until) if until > now => KeyStatus::CoolingDown(until - now),
+            _ => KeyStatus::Healthy,
+        }
+    }
+}
+
+/// Every key a client can send requests with, handed out round robin.
+///
+/// Taking a key is an atomic increment, and the keys are swapped out as a whole when the accounts
+/// log in again, so requests never see a half-updated rotation. Keys that are cooling down or
+/// quarantined are skipped.
+#[derive(Debug, Default)]
+pub(crate) struct KeyRing {
+    keys: ArcSwap<Vec<RingKey>>,
+    next: AtomicUsize,
+    /// By token, so that a key keeps its record when the ring is rebuilt
+    health: DashMap<Secret, KeyHealth>,
+}
+
+impl KeyRing {
+    /// A ring of pre-issued keys that don't belong to an account
+    pub(crate) fn with_tokens(tokens: Vec<String>) -> Self {
+        let keys = tokens
+            .into_iter()
+            .enumerate()
+            .map(|(index, token)| RingKey {
+                id: format!("static-{index}"),
+                account: None,
+                token: Secret::new(token),
+            })
+            .collect::<Vec<_>>();
+        let ring = Self::default();
+        ring.replace(keys);
+        ring
+    }
+
+    /// Replaces every key in the rotation, forgetting the records of keys that are gone
+    pub(crate) fn replace(&self, keys: Vec<RingKey>) {
+        self.health.retain(|token, _| keys.iter().any(|key| &key.token == token));
+        self.keys.store(Arc::new(keys));
+    }
+
+    /// The next usable key in the rotation. Otherwise how long until a key's cooldown runs out,
+    /// or `None` if there isn't any key left to wait for.
+    pub(crate) fn next(&self) -> Result<Secret, Option<Duration>> {
+        let keys = self.keys.load();
+        let now = Instant::now();
+        let mut wait = None::<Duration>;
+
+        for _ in 0..keys.len() {
+            let key = &keys[self.next.fetch_add(1, Ordering::Relaxed) % keys.len()];
+            let status = self.health.get(&key.token).map_or(KeyStatus::Healthy, |h| h.status(now));
+            match status {
+                KeyStatus::Healthy => return Ok(key.token.clone()),
+                KeyStatus::CoolingDown(left) => wait = Some(wait.map_or(left, |w| w.min(left))),
+                KeyStatus::Quarantined => {}
+            }
+        }
+
+        Err(wait)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.keys.load().len()
+    }
+
+    /// Records the outcome of a request sent with the key, returning whether the key has just
+    /// been quarantined after `quarantine_after` rejections in a row
+    pub(crate) fn record(
+        &self,
+        token: &Secret,
+        outcome: Outcome,
+        latency: Duration,
+        quarantine_after: u32,
+    ) -> bool {
+        if !self.keys.load().iter().any(|key| &key.token == token) {
+            return false;
+        }
+
+        let mut health = self.health.entry(token.clone()).or_default();
+        health.responses = health.responses.saturating_add(1);
+        health.total_latency = health.total_latency.saturating_add(latency);
+        if outcome != Outcome::Rejected {
+            health.consecutive_rejections = 0;
+        }
+        match outcome {
+            Outcome::Success => health.successes += 1,
+            Outcome::Throttled(cooldown) => {
+                health.throttled += 1;
+                health.cooldown_until = Some(Instant::now() + cooldown);
+            }
+            Outcome::Rejected => {
+                health.rejected += 1;
+                health.consecutive_rejections += 1;
+                if !health.quarantined && health.consecutive_rejections >= quarantine_after {
+                    health.quarantined = true;
+                    return true;
+                }
+            }
+            Outcome::Responded => {}
+        }
+        false
+    }
+
+    pub(crate) fn stats(&self) -> Vec<KeyStats> {
+        let now = Instant::now();
+        self.keys
+            .load()
+            .iter()
+            .map(|key| {
+                let health = self.health.get(&key.token).map(|h| h.clone()).unwrap_or_default();
+                KeyStats {
+                    id: key.id.clone(),
+                    account: key.account.clone(),
+                    status: health.status(now),
+                    successes: health.successes,
+                    throttled: health.throttled,
+                    rejected: health.rejected,
+                    average_latency: (health.responses > 0)
+                        .then(|| health.total_latency / health.responses),
+                }
+            })
+            .collect()
+    }
+}
@@ -1,6 +1,9 @@
 /// Base API wrapper
 pub mod api;
 
+/// Response caching
+pub mod cache;
+
 /// API models
 mod models;
 pub use models::*;
@@ -18,7 +21,7 @@ pub use cos_models::*;
 /// To structure a login
 pub mod credentials;
 
-/// Developer Site API wrapper
+/// Keys of the accounts a client logs into
 mod dev;
 
 /// API + Clash of Stats Errors
@@ -27,6 +30,27 @@ pub mod error;
 /// Events track changes in the API
 pub mod events;
 
+/// Developer Site API wrapper
+pub mod portal;
+
+/// Rotation and health of the keys requests are sent with
+pub mod keyring;
+
+/// Per-key rate limiting
+mod ratelimit;
+
+/// Retry and backoff policies
+pub mod retry;
+
+/// Redacted passwords and API tokens
+pub mod secret;
+
+/// Persisting logged in keys across restarts
+pub mod session;
+
+/// Pluggable HTTP transport
+pub mod transport;
+
 #[cfg(feature = "extra")]
 pub mod util;
 
@@ -38,7 +62,12 @@ extern crate num_derive;
 
 #[cfg(test)]
 mod tests {
-    use std::{env, time::Instant};
+    use std::{
+        collections::{HashMap, VecDeque},
+        env,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
 
     use anyhow::{Context, Result};
     use async_trait::async_trait;
@@ -46,11 +75,18 @@ mod tests {
 
     use crate::{
         api::Client,
+        cache::MemoryCache,
         credentials::Credentials,
-        error::APIError,
+        error::{APIError, LoginError},
         events::{EventHandler, EventType, EventsListenerBuilder},
+        keyring::{KeyRing, KeyStatus, Outcome, RingKey},
         location::Local,
         models::{clan, clan_search, leagues, location, paging, player, season},
+        portal::{DeveloperPortal, Key, KeySettings, KeySpec, TemporaryAPIToken},
+        ratelimit::TokenBucket,
+        retry::{RetryOn, RetryPolicy},
+        session::{AccountSnapshot, SessionSnapshot},
+        transport::HttpTransport,
     };
 
     static mut LOADED: bool = false;
@@ -65,6 +101,268 @@ mod tests {
         env::var(key).with_context(|| format!("Missing environment variable {key}"))
     }
 
+    /// URL and authorization header of a request sent through [`MockTransport`]
+    type SentRequest = (String, Option<String>);
+
+    /// Serves canned responses in order and records the URL and authorization header of every
+    /// request it receives
+    #[derive(Clone, Debug, Default)]
+    struct MockTransport {
+        responses: Arc<parking_lot::Mutex<VecDeque<http::Response<String>>>>,
+        requests: Arc<parking_lot::Mutex<Vec<SentRequest>>>,
+        latency: Duration,
+    }
+
+    impl MockTransport {
+        fn new(responses: Vec<(u16, &str)>) -> Self {
+            let transport = Self::default();
+            for (status, body) in responses {
+                transport.push(status, &[], body);
+            }
+            transport
+        }
+
+        fn with_latency(self, latency: Duration) -> Self {
+            Self { latency, ..self }
+        }
+
+        fn push(&self, status: u16, headers: &[(&str, &str)], body: &str) {
+            let mut response = http::Response::builder().status(status);
+            for (name, value) in headers {
+                response = response.header(*name, *value);
+            }
+            self.responses.lock().push_back(response.body(body.to_owned()).unwrap());
+        }
+
+        fn requests(&self) -> Vec<SentRequest> {
+            self.requests.lock().clone()
+        }
+    }
+
+    #[async_trait]
+    impl HttpTransport for MockTransport {
+        async fn execute(&self, request: reqwest::Request) -> Result<reqwest::Response, APIError> {
+            let authorization = request
+                .headers()
+                .get(reqwest::header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .map(ToString::to_string);
+            self.requests.lock().push((request.url().to_string(), authorization));
+            tokio::time::sleep(self.latency).await;
+
+            let response = self.responses.lock().pop_front();
+            Ok(response
+                .unwrap_or_else(|| {
+                    http::Response::builder().status(404).body(String::new()).unwrap()
+                })
+                .into())
+        }
+    }
+
+    /// The smallest body an endpoint answers with, a gold pass season
+    const GOLD_PASS: &str =
+        r#"{"startTime":"20230901T080000.000Z","endTime":"20231001T080000.000Z"}"#;
+
+    /// The credentials of one account
+    fn single_credential() -> Credentials {
+        Credentials::builder().add_credential("user1".to_owned(), "pass1".to_owned()).build()
+    }
+
+    /// A snapshot with one key of that account, created for `203.0.113.7`
+    fn restored_snapshot() -> SessionSnapshot {
+        SessionSnapshot {
+            ip_address: "203.0.113.7/32".to_owned(),
+            accounts: vec![AccountSnapshot {
+                email: "user1".to_owned(),
+                keys: vec![Key { key: "restored".into(), ..Key::default() }],
+            }],
+        }
+    }
+
+    /// A key that may be used from these CIDR ranges
+    fn key_for(cidr_ranges: &[&str]) -> Key {
+        Key { cidr_ranges: cidr_ranges.iter().map(ToString::to_string).collect(), ..Key::default() }
+    }
+
+    /// What a [`FakePortal`] has been asked to do, and the accounts it holds
+    #[derive(Default)]
+    struct PortalState {
+        /// The address logins come from
+        ip: String,
+        /// Emails whose password is refused
+        refused: Vec<String>,
+        /// The email of every successful login
+        logins: Vec<String>,
+        keys: HashMap<String, Vec<Key>>,
+        created: usize,
+    }
+
+    /// A developer portal on a local port, with one session cookie per account and the portal's
+    /// limit of keys per account
+    struct FakePortal {
+        base_url: String,
+        state: Arc<parking_lot::Mutex<PortalState>>,
+    }
+
+    impl FakePortal {
+        async fn start(ip: &str) -> anyhow::Result<Self> {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+            let base_url = format!("http://{}", listener.local_addr()?);
+            let state = Arc::new(parking_lot::Mutex::new(PortalState {
+                ip: ip.to_owned(),
+                ..PortalState::default()
+            }));
+            tokio::spawn({
+                let state = state.clone();
+                async move {
+                    while let Ok((stream, _)) = listener.accept().await {
+                        tokio::spawn(Self::serve(stream, state.clone()));
+                    }
+                }
+            });
+
+            Ok(Self { base_url, state })
+        }
+
+        fn set_ip(&self, ip: &str) {
+            self.state.lock().ip = ip.to_owned();
+        }
+
+        fn keys(&self, email: &str) -> Vec<Key> {
+            self.state.lock().keys.get(email).cloned().unwrap_or_default()
+        }
+
+        fn refuse(&self, email: &str) {
+            self.state.lock().refused.push(email.to_owned());
+        }
+
+        fn accept(&self, email: &str) {
+            self.state.lock().refused.retain(|refused| refused != email);
+        }
+
+        fn logins(&self) -> Vec<String> {
+            self.state.lock().logins.clone()
+        }
+
+        async fn serve(
+            mut stream: tokio::net::TcpStream,
+            state: Arc<parking_lot::Mutex<PortalState>>,
+        ) {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            let (head, body) = loop {
+                let Ok(read) = stream.read(&mut buffer).await else { return };
+                if read == 0 {
+                    return;
+                }
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).into_owned();
+                let Some((head, body)) = text.split_once("\r\n\r\n") else { continue };
+                let length = head
+                    .lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                    .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+                    .unwrap_or_default();
+                if body.len() >= length {
+                    break (head.to_owned(), body.to_owned());
+                }
+            };
+
+            let (status, cookie, body) = Self::respond(&head, &body, &mut state.lock());
+            let cookie = cookie.map(|email| format!("set-cookie: session={email}\r\n"));
+            let response = format!(
+                "HTTP/1.1 {status}\r\n{}content-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                cookie.unwrap_or_default(),
+                body.len()
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+
+        fn respond(
+            head: &str,
+            body: &str,
+            state: &mut PortalState,
+        ) -> (&'static str, Option<String>, String) {
+            use base64::Engine;
+
+            let json = serde_json::from_str::<serde_json::Value>(body).unwrap_or_default();
+            let path = head.split(' ').nth(1).unwrap_or_default();
+            let session = head
+                .lines()
+                .find_map(|line| {
+                    line.to_lowercase().strip_prefix("cookie: session=").map(str::to_owned)
+                })
+                .unwrap_or_default();
+            let ok = |key: Option<&Key>| {
+                serde_json::json!({
+                    "status": { "code": 0, "message": "ok" },
+                    "sessionExpiresInSeconds": 3600,
+                    "key": key,
+                })
+                .to_string()
+            };
+
+            match path {
+                "/api/login" => {
+                    let email = json["email"].as_str().unwrap_or_default().to_owned();
+                    if state.refused.contains(&email) {
+                        return ("403 Forbidden", None, String::new());
+                    }
+                    state.logins.push(email.clone());
+                    let claims = serde_json::json!({
+                        "iss": "supercell", "aud": "supercell:gameapi", "jti": "jti",
+                        "iat": 0, "exp": 3600, "sub": "developer/fake", "scopes": ["clash"],
+                        "limits": [{ "cidrs": [format!("{}/32", state.ip)], "type": "client" }],
+                    });
+                    let claims = paging::BASE64_ENGINE.encode(claims.to_string());
+                    let response = serde_json::json!({
+                        "status": { "code": 0, "message": "ok" },
+                        "sessionExpiresInSeconds": 3600,
+                        "auth": { "uid": "fake", "token": "session" },
+                        "developer": {
+                            "id": "fake", "name": "fake", "game": "clash", "email": email,
+                            "tier": "developer/silver", "prevLoginTs": "", "prevLoginIp": "",
+                            "prevLoginUa": "",
+                        },
+                        "temporaryAPIToken": format!("eyJ0eXAiOiJKV1QifQ.{claims}.signature"),
+                        "swaggerUrl": "",
+                    });
+                    ("200 OK", Some(email), response.to_string())
+                }
+                "/api/apikey/list" => {
+                    let keys = state.keys.get(&session).cloned().unwrap_or_default();
+                    ("200 OK", None, serde_json::json!({ "keys": keys }).to_string())
+                }
+                "/api/apikey/create" => {
+                    if state.keys.get(&session).map_or(0, Vec::len) >= DeveloperPortal::MAX_KEYS {
+                        return ("400 Bad Request", None, String::new());
+                    }
+                    state.created += 1;
+                    let key = Key {
+                        id: format!("id-{}", state.created),
+                        name: json["name"].as_str().unwrap_or_default().to_owned(),
+                        description: json["description"].as_str().map(ToOwned::to_owned),
+                        cidr_ranges: serde_json::from_value(json["cidrRanges"].clone())
+                            .unwrap_or_default(),
+                        key: format!("token-{}", state.created).into(),
+                        ..Key::default()
+                    };
+                    state.keys.entry(session).or_default().push(key.clone());
+                    ("200 OK", None, ok(Some(&key)))
+                }
+                "/api/apikey/revoke" => {
+                    let id = json["id"].as_str().unwrap_or_default();
+                    state.keys.entry(session).or_default().retain(|key| key.id != id);
+                    ("200 OK", None, ok(None))
+                }
+                _ => ("404 Not Found", None, String::new()),
+            }
+        }
+    }
+
     async fn load_client() -> anyhow::Result<()> {
         unsafe {
             if !LOADED {
@@ -102,6 +400,894 @@ mod tests {
         assert_eq!(credentials.0[1].password(), "pass2");
     }
 
+    #[test]
+    fn test_secrets() -> anyhow::Result<()> {
+        let credentials =
+            Credentials::builder().add_credential("user1".to_owned(), "hunter2".to_owned()).build();
+        assert!(!format!("{credentials:?}").contains("hunter2"));
+        assert_eq!(credentials.0[0].password().expose_secret(), "hunter2");
+
+        let key = Key { id: "1".to_owned(), key: "eyJ0eXAi".into(), ..Key::default() };
+        assert!(!format!("{key}").contains("eyJ0eXAi") && !format!("{key:?}").contains("eyJ0eXAi"));
+        // snapshots still hold the key itself
+        let json = serde_json::to_string(&key)?;
+        assert!(json.contains(r#""key":"eyJ0eXAi""#));
+        assert_eq!(serde_json::from_str::<Key>(&json)?.key.expose_secret(), "eyJ0eXAi");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_spec() -> anyhow::Result<()> {
+        let spec = KeySpec::new("bot").description("prod").cidr("203.0.113.7").cidr("10.0.0.0/8");
+        assert_eq!(
+            serde_json::to_string(&spec)?,
+            r#"{"name":"bot","description":"prod","cidrRanges":["203.0.113.7","10.0.0.0/8"],"scopes":["clash"]}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_settings() {
+        let key = |name: &str, description: Option<&str>| Key {
+            name: name.to_owned(),
+            description: description.map(ToOwned::to_owned),
+            ..Key::default()
+        };
+        let ours = key("coc-rs", Some("Created on 2023-09-01T08:00:00+00:00 by coc.rs"));
+        let foreign = key("staging server", Some("made by hand"));
+        let unmarked = key("coc-rs", None);
+
+        let settings = KeySettings::default();
+        assert!(settings.owns(&ours));
+        assert!(!settings.owns(&foreign) && !settings.manages(&foreign));
+        assert!(!settings.owns(&unmarked));
+        assert!(settings.clone().manage_foreign_keys(true).manages(&foreign));
+
+        let custom = KeySettings::new().name_prefix("bot-eu").description_marker("eu cluster");
+        assert!(!custom.owns(&ours));
+        assert!(custom.owns(&key("bot-eu-1", Some("Created by eu cluster"))));
+    }
+
+    #[test]
+    fn test_key_settings_templates() -> anyhow::Result<()> {
+        let settings = KeySettings::new()
+            .name_prefix("bot")
+            .name_template("{prefix}-{ip}")
+            .description_template("{marker} key of {email}")
+            .key_count(8)
+            .reserved_slots(3);
+        assert_eq!(settings.max_keys(), 7);
+        assert_eq!(KeySettings::new().key_count(4).max_keys(), 4);
+        assert_eq!(KeySettings::new().key_count(20).max_keys(), 10);
+
+        let spec =
+            serde_json::to_value(settings.spec("me@example.com", &["203.0.113.7/32".to_owned()]))?;
+        assert_eq!(spec["name"], "bot-203.0.113.7/32");
+        assert_eq!(spec["description"], "coc.rs key of me@example.com");
+
+        let key = Key {
+            name: spec["name"].as_str().unwrap_or_default().to_owned(),
+            description: spec["description"].as_str().map(ToOwned::to_owned),
+            ..Key::default()
+        };
+        assert!(settings.owns(&key));
+
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "description_template must contain {marker}")]
+    fn test_key_settings_unmarked_template() {
+        let _ = KeySettings::new().description_template("Created on {date}");
+    }
+
+    #[test]
+    fn test_temporary_api_token() -> anyhow::Result<()> {
+        use base64::Engine;
+
+        let jwt = |claims: &str| {
+            let claims = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims);
+            serde_json::to_string(&format!("eyJ0eXAiOiJKV1QifQ.{claims}.signature"))
+        };
+        // the client limit isn't the second one
+        let claims = r#"{"iss":"supercell","aud":"supercell:gameapi","jti":"6b59b631","iat":1693633017,"exp":1693636617,"sub":"developer/54161cdf","scopes":["clash"],"limits":[{"cidrs":["203.0.113.7/32"],"type":"client"},{"tier":"developer/bronze","type":"throttling"}]}"#;
+        let token = serde_json::from_str::<TemporaryAPIToken>(&jwt(claims)?)?;
+        assert_eq!(token.exp - token.iat, 3600);
+        let cidrs = token.client_limit().and_then(|limit| limit.cidrs.clone());
+        assert_eq!(cidrs, Some(vec!["203.0.113.7/32".to_owned()]));
+
+        // a layout change is an error rather than a panic
+        let claims = r#"{"iss":"supercell","iat":"yesterday"}"#;
+        let error = serde_json::from_str::<TemporaryAPIToken>(&jwt(claims)?).unwrap_err();
+        assert!(error.to_string().contains("unexpected claims in the token"));
+        assert!(serde_json::from_str::<TemporaryAPIToken>(r#""not a jwt""#).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_allows() {
+        let ip = |ip: &str| ip.parse::<std::net::IpAddr>().unwrap();
+
+        assert!(key_for(&["203.0.113.7"]).allows(ip("203.0.113.7")));
+        assert!(key_for(&["203.0.113.7/32"]).allows(ip("203.0.113.7")));
+        // string containment would accept these
+        assert!(!key_for(&["3.0.113.7"]).allows(ip("203.0.113.7")));
+        assert!(!key_for(&["203.0.113.7"]).allows(ip("203.0.113.70")));
+
+        let ranges = key_for(&["10.0.0.0/8", "203.0.113.0/24", "2001:db8::/32", "not a cidr"]);
+        assert_eq!(ranges.networks().len(), 3);
+        assert!(ranges.allows(ip("203.0.113.254")));
+        assert!(ranges.allows(ip("10.20.30.40")));
+        assert!(ranges.allows(ip("2001:db8:1::1")));
+        assert!(!ranges.allows(ip("203.0.114.1")));
+        assert!(!ranges.allows(ip("2001:db9::1")));
+    }
+
+    #[test]
+    fn test_egress_ips() -> anyhow::Result<()> {
+        let client = Client::builder()
+            .egress_ips(&["203.0.113.7", "198.51.100.0/24", "203.0.113.7/32"])
+            .build_with_tokens(vec!["token".to_owned()])?;
+        let cidrs = client.config.egress_cidrs("192.0.2.1/32");
+        assert_eq!(cidrs, ["203.0.113.7/32", "198.51.100.0/24"]);
+        assert_eq!(
+            Client::with_tokens(vec!["token".to_owned()])?.config.egress_cidrs("192.0.2.1/32"),
+            ["192.0.2.1/32"]
+        );
+
+        // one key for every address
+        let spec = serde_json::to_value(
+            KeySettings::new().description_template("{marker} {ip}").spec("me@example.com", &cidrs),
+        )?;
+        assert_eq!(spec["cidrRanges"], serde_json::json!(["203.0.113.7/32", "198.51.100.0/24"]));
+        assert_eq!(spec["description"], "coc.rs 203.0.113.7/32, 198.51.100.0/24");
+
+        let network = "198.51.100.0/24".parse()?;
+        assert!(key_for(&["198.51.100.0/23"]).covers(&network));
+        assert!(!key_for(&["198.51.100.7"]).covers(&network));
+
+        // a typo in the configuration is an error rather than a panic
+        let result = Client::builder()
+            .egress_ips(&["203.0.113.7", "203.0.113.700"])
+            .build_with_tokens(vec!["token".to_owned()]);
+        assert!(
+            matches!(result, Err(APIError::InvalidParameters(message)) if message.contains("203.0.113.700"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_tokens() {
+        assert!(matches!(Client::with_tokens(vec![]), Err(APIError::InvalidParameters(_))));
+        assert!(Client::with_tokens(vec!["token1".to_owned(), "token2".to_owned()]).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_transport() -> anyhow::Result<()> {
+        let transport = MockTransport::new(vec![(200, GOLD_PASS)]);
+        let client = Client::builder()
+            .base_url("http://localhost:8080/v1/")
+            .transport(transport.clone())
+            .build_with_tokens(vec!["token".to_owned()])?;
+
+        let gold_pass = client.get_goldpass().await?;
+        assert_eq!(gold_pass.start_time().to_rfc3339(), "2023-09-01T08:00:00+00:00");
+        assert!(matches!(client.get_clan("2PP").await, Err(APIError::NotFound(_))));
+
+        assert_eq!(
+            transport.requests(),
+            vec![
+                (
+                    "http://localhost:8080/v1/goldpass/seasons/current".to_owned(),
+                    Some("Bearer token".to_owned())
+                ),
+                (
+                    "http://localhost:8080/v1/clans/%232PP".to_owned(),
+                    Some("Bearer token".to_owned())
+                ),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_ring() {
+        assert_eq!(KeyRing::default().next(), Err(None));
+
+        let tokens = vec!["a".to_owned(), "b".to_owned(), "c".to_owned()];
+        let ring = Arc::new(KeyRing::with_tokens(tokens));
+        let threads = (0..6)
+            .map(|_| {
+                let ring = ring.clone();
+                std::thread::spawn(move || {
+                    (0..1000).filter_map(|_| ring.next().ok()).collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut counts = std::collections::HashMap::new();
+        for key in threads.into_iter().flat_map(|thread| thread.join().unwrap()) {
+            *counts.entry(key).or_insert(0) += 1;
+        }
+        assert!(counts.values().all(|count| *count == 2000));
+
+        // a throttled key rests, a rejected one is quarantined after 2 rejections in a row
+        let latency = Duration::from_millis(10);
+        assert!(!ring.record(&"a".into(), Outcome::Throttled(Duration::from_secs(60)), latency, 2));
+        assert!(!ring.record(&"b".into(), Outcome::Rejected, latency, 2));
+        assert!(ring.record(&"b".into(), Outcome::Rejected, latency, 2));
+        assert!(!ring.record(&"b".into(), Outcome::Rejected, latency, 2));
+        assert!(!ring.record(&"c".into(), Outcome::Success, latency * 3, 2));
+        assert!((0..6).all(|_| ring.next() == Ok("c".into())));
+
+        let stats = ring.stats();
+        assert!(matches!(stats[0].status, KeyStatus::CoolingDown(_)));
+        assert_eq!(stats[0].throttled, 1);
+        assert_eq!((stats[1].status, stats[1].rejected), (KeyStatus::Quarantined, 3));
+        assert_eq!((stats[2].id.as_str(), stats[2].status), ("static-2", KeyStatus::Healthy));
+        assert_eq!(stats[2].average_latency, Some(latency * 3));
+
+        ring.record(&"c".into(), Outcome::Throttled(Duration::from_millis(200)), latency, 2);
+        assert!(matches!(ring.next(), Err(Some(wait)) if wait <= Duration::from_millis(200)));
+
+        ring.replace(vec![RingKey { id: "1".to_owned(), account: None, token: "d".into() }]);
+        assert_eq!(ring.next(), Ok("d".into()));
+        assert_eq!(ring.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_key_cooldown() -> anyhow::Result<()> {
+        let transport = MockTransport::default();
+        transport.push(429, &[("retry-after", "60")], "");
+        for _ in 0..3 {
+            transport.push(200, &[], GOLD_PASS);
+        }
+        let client = Client::builder()
+            .transport(transport.clone())
+            .coalesce_requests(false)
+            .build_with_tokens(vec!["a".to_owned(), "b".to_owned()])?;
+
+        assert!(matches!(client.get_goldpass().await, Err(APIError::RequestThrottled(_))));
+        for _ in 0..3 {
+            client.get_goldpass().await?;
+        }
+        let keys = transport.requests().into_iter().filter_map(|(_, key)| key).collect::<Vec<_>>();
+        assert_eq!(keys, ["Bearer a", "Bearer b", "Bearer b", "Bearer b"]);
+
+        let stats = client.key_stats();
+        assert!(matches!(stats[0].status, KeyStatus::CoolingDown(_)));
+        assert_eq!((stats[0].throttled, stats[1].successes), (1, 3));
+
+        // a revoked key is quarantined instead of being handed out again
+        let transport = MockTransport::new(vec![
+            (403, r#"{"reason":"accessDenied","message":"Invalid authorization"}"#),
+            (200, GOLD_PASS),
+            (200, GOLD_PASS),
+        ]);
+        let client = Client::builder()
+            .transport(transport.clone())
+            .coalesce_requests(false)
+            .quarantine_after(1)
+            .build_with_tokens(vec!["a".to_owned(), "b".to_owned()])?;
+
+        assert!(matches!(client.get_goldpass().await, Err(APIError::AccessDenied(_))));
+        client.get_goldpass().await?;
+        client.get_goldpass().await?;
+        let keys = transport.requests().into_iter().filter_map(|(_, key)| key).collect::<Vec<_>>();
+        assert_eq!(keys, ["Bearer a", "Bearer b", "Bearer b"]);
+        assert_eq!(client.key_stats()[0].status, KeyStatus::Quarantined);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replace_quarantined_key() -> anyhow::Result<()> {
+        let invalid_key = r#"{"reason":"accessDenied","message":"Invalid authorization"}"#;
+        let replaced = |portal: &FakePortal, token: &str| {
+            let keys = portal.keys("user1");
+            keys.len() == DeveloperPortal::MAX_KEYS && keys.iter().all(|key| key.key != *token)
+        };
+        let wait = |client: &Client, token: &str| {
+            let (client, token) = (client.clone(), token.to_owned());
+            async move {
+                for _ in 0..100 {
+                    let session = client.export_session();
+                    let keys = &session.accounts[0].keys;
+                    if keys.len() == DeveloperPortal::MAX_KEYS
+                        && keys.iter().all(|k| k.key != *token)
+                    {
+                        return;
+                    }
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+                panic!("the quarantined key wasn't replaced");
+            }
+        };
+
+        // every slot of the account is taken, so the old key has to go first
+        let portal = FakePortal::start("203.0.113.7").await?;
+        let transport = MockTransport::new(vec![(403, invalid_key)]);
+        let client = Client::builder()
+            .transport(transport.clone())
+            .dev_base_url(&portal.base_url)
+            .quarantine_after(1)
+            .build(single_credential())
+            .await?;
+        assert_eq!(portal.keys("user1").len(), DeveloperPortal::MAX_KEYS);
+        assert!(client.get_goldpass().await.is_err());
+        let rejected = transport.requests()[0].1.clone().unwrap_or_default();
+        let rejected = rejected.trim_start_matches("Bearer ");
+        wait(&client, rejected).await;
+        assert!(replaced(&portal, rejected));
+
+        // a restored account logs in once one of its keys is rejected
+        let portal = FakePortal::start("203.0.113.7").await?;
+        let client = Client::builder()
+            .transport(MockTransport::new(vec![(403, invalid_key)]))
+            .dev_base_url(&portal.base_url)
+            .quarantine_after(1)
+            .restore(single_credential(), restored_snapshot())
+            .await?;
+        assert!(portal.logins().is_empty());
+        assert!(client.get_goldpass().await.is_err());
+        wait(&client, "restored").await;
+        assert_eq!(portal.logins(), ["user1"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_key_rotation() -> anyhow::Result<()> {
+        let keys = |keys: &[&str]| {
+            keys.iter().map(|key| Key { key: (*key).into(), ..Key::default() }).collect()
+        };
+        let snapshot = SessionSnapshot {
+            ip_address: "203.0.113.7/32".to_owned(),
+            accounts: vec![
+                AccountSnapshot { email: "user1".to_owned(), keys: keys(&["a1", "a2", "a3"]) },
+                AccountSnapshot { email: "user2".to_owned(), keys: keys(&["b1"]) },
+            ],
+        };
+        let credentials = Credentials::builder()
+            .add_credential("user1".to_owned(), "pass1".to_owned())
+            .add_credential("user2".to_owned(), "pass2".to_owned())
+            .build();
+        let transport = MockTransport::default();
+        let client = Client::builder()
+            .transport(transport.clone())
+            .coalesce_requests(false)
+            .restore(credentials, snapshot)
+            .await?;
+
+        for _ in 0..5 {
+            let _ = client.get_goldpass().await;
+        }
+        let keys = transport.requests().into_iter().filter_map(|(_, key)| key).collect::<Vec<_>>();
+        assert_eq!(keys, ["Bearer a1", "Bearer b1", "Bearer a2", "Bearer a3", "Bearer a1"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let bucket = TokenBucket::new(2.0);
+        assert!(bucket.try_acquire().is_ok());
+        assert!(bucket.try_acquire().is_ok());
+        let refill = bucket.try_acquire().unwrap_err();
+        assert!(refill > Duration::ZERO && refill <= Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() -> anyhow::Result<()> {
+        let transport = MockTransport::new(vec![(200, GOLD_PASS); 12]);
+        let client = Client::builder()
+            .transport(transport.clone())
+            .rate_limit(10.0)
+            .build_with_tokens(vec!["token".to_owned()])?;
+
+        // the first 10 requests drain the bucket, the last 2 have to wait for it to refill
+        let now = Instant::now();
+        for _ in 0..12 {
+            client.get_goldpass().await?;
+        }
+        assert!(now.elapsed() >= Duration::from_millis(180));
+        assert_eq!(transport.requests().len(), 12);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_policy() -> anyhow::Result<()> {
+        let transport = MockTransport::default();
+        transport.push(503, &[], "");
+        transport.push(429, &[("Retry-After", "0")], "");
+        transport.push(200, &[], GOLD_PASS);
+        transport.push(404, &[], "");
+        let client = Client::builder()
+            .transport(transport.clone())
+            .retry_policy(RetryPolicy::new().base_delay(Duration::ZERO))
+            .build_with_tokens(vec!["token1".to_owned(), "token2".to_owned()])?;
+
+        client.get_goldpass().await?;
+        assert!(matches!(client.get_goldpass().await, Err(APIError::NotFound(_))));
+
+        // every retry is re-authorized with the next key
+        let keys = transport.requests().into_iter().map(|(_, key)| key).collect::<Vec<_>>();
+        assert_eq!(keys.len(), 4);
+        assert_ne!(keys[0], keys[1]);
+        assert_ne!(keys[1], keys[2]);
+
+        let transport = MockTransport::new(vec![(503, ""); 3]);
+        let client = Client::builder()
+            .transport(transport.clone())
+            .retry_policy(
+                RetryPolicy::new().base_delay(Duration::ZERO).retry_on(&[RetryOn::Throttled]),
+            )
+            .build_with_tokens(vec!["token".to_owned()])?;
+        assert!(matches!(client.get_goldpass().await, Err(APIError::InMaintenance(_))));
+        assert_eq!(transport.requests().len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cache() -> anyhow::Result<()> {
+        let transport = MockTransport::default();
+        transport.push(200, &[("Cache-Control", "max-age=0")], GOLD_PASS);
+        transport.push(200, &[], GOLD_PASS);
+        transport.push(200, &[("Cache-Control", "public, max-age=60")], GOLD_PASS);
+        let client = Client::builder()
+            .transport(transport.clone())
+            .cache(MemoryCache::default())
+            .build_with_tokens(vec!["token".to_owned()])?;
+
+        // only the last response may be cached, so the fourth request is served from the cache
+        for _ in 0..4 {
+            client.get_goldpass().await?;
+        }
+        assert_eq!(transport.requests().len(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_requests() -> anyhow::Result<()> {
+        let transport = MockTransport::new(vec![(200, GOLD_PASS), (200, GOLD_PASS), (404, "")])
+            .with_latency(Duration::from_millis(50));
+        let client = Client::builder()
+            .transport(transport.clone())
+            .build_with_tokens(vec!["token".to_owned()])?;
+
+        let (first, second, third) =
+            tokio::join!(client.get_goldpass(), client.get_goldpass(), client.get_goldpass());
+        assert!(first.is_ok() && second.is_ok() && third.is_ok());
+        assert_eq!(transport.requests().len(), 1);
+
+        // once the request has completed, the next one is sent on its own
+        client.get_goldpass().await?;
+        assert_eq!(transport.requests().len(), 2);
+
+        // errors are shared too
+        let (first, second) = tokio::join!(client.get_goldpass(), client.get_goldpass());
+        assert!(matches!(first, Err(APIError::NotFound(_))));
+        assert!(matches!(second, Err(APIError::NotFound(_))));
+        assert_eq!(transport.requests().len(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bulk_fetch() -> anyhow::Result<()> {
+        let transport = MockTransport::default().with_latency(Duration::from_millis(50));
+        let client = Client::builder()
+            .transport(transport.clone())
+            .bulk_concurrency(2)
+            .build_with_tokens(vec!["token".to_owned()])?;
+
+        let now = Instant::now();
+        let players = client.get_players(&["2PP", "8QU8J9LP", "P2P2P2P2", "not a tag"]).await;
+        assert_eq!(players.len(), 4);
+        assert!(matches!(players["2PP"], Err(APIError::NotFound(_))));
+        assert!(matches!(players["not a tag"], Err(APIError::InvalidTag(_))));
+
+        // three requests, two at a time
+        assert_eq!(transport.requests().len(), 3);
+        assert!(now.elapsed() >= Duration::from_millis(100));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deserialize_error() -> anyhow::Result<()> {
+        let transport =
+            MockTransport::new(vec![(200, r#"{"startTime":20230901,"endTime":"20231001"}"#)]);
+        let client =
+            Client::builder().transport(transport).build_with_tokens(vec!["token".to_owned()])?;
+
+        match client.get_goldpass().await {
+            Err(APIError::Deserialize { path, body, .. }) => {
+                assert_eq!(path, "startTime");
+                assert!(body.contains("20231001"));
+            }
+            result => panic!("expected a deserialize error, got {result:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_error_body() -> anyhow::Result<()> {
+        let transport = MockTransport::new(vec![
+            (404, r#"{"reason":"notFound","message":"Resource was not found."}"#),
+            (503, "<html>maintenance</html>"),
+        ]);
+        let client =
+            Client::builder().transport(transport).build_with_tokens(vec!["token".to_owned()])?;
+
+        match client.get_goldpass().await {
+            Err(APIError::NotFound(Some(body))) => {
+                assert_eq!(body.reason, "notFound");
+                assert_eq!(body.message.as_deref(), Some("Resource was not found."));
+            }
+            result => panic!("expected a not found error with a body, got {result:?}"),
+        }
+        assert!(matches!(client.get_goldpass().await, Err(APIError::InMaintenance(None))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_access_denied() -> anyhow::Result<()> {
+        let private =
+            r#"{"reason":"accessDenied","message":"Access denied, clan war log is private."}"#;
+        let transport = MockTransport::new(vec![
+            (403, private),
+            (403, private),
+            (403, r#"{"reason":"privateWarLog"}"#),
+            (403, r#"{"reason":"accessDenied.invalidIp","message":"Invalid authorization"}"#),
+            // e.g. a proxy's error page, or a reason the API may add later
+            (403, "<html>Forbidden</html>"),
+            (403, r#"{"reason":"accessDenied.unknown"}"#),
+        ]);
+        let client = Client::builder()
+            .transport(transport.clone())
+            .build_with_tokens(vec!["token".to_owned()])?;
+
+        assert!(matches!(client.get_clan_warlog("2PP").await, Err(APIError::PrivateWarLog)));
+        assert!(matches!(client.get_current_war("2PP").await, Err(APIError::PrivateWarLog)));
+        assert!(matches!(client.get_current_war("2PP").await, Err(APIError::PrivateWarLog)));
+        assert!(matches!(client.get_clan_warlog("2PP").await, Err(APIError::StaticKeyRejected)));
+        assert!(matches!(client.get_clan_warlog("2PP").await, Err(APIError::AccessDenied(None))));
+        assert!(matches!(
+            client.get_current_war("2PP").await,
+            Err(APIError::AccessDenied(Some(body))) if body.reason == "accessDenied.unknown"
+        ));
+        assert_eq!(transport.requests().len(), 6);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_restore_session() -> anyhow::Result<()> {
+        let snapshot = serde_json::from_value::<SessionSnapshot>(serde_json::json!({
+            "ip_address": "203.0.113.7/32",
+            "accounts": [{
+                "email": "user1",
+                "keys": [{
+                    "id": "1",
+                    "developerId": "dev",
+                    "tier": "developer/silver",
+                    "name": "coc-rs",
+                    "description": "Created by coc.rs",
+                    "scopes": ["clash"],
+                    "cidrRanges": ["203.0.113.7"],
+                    "key": "restored",
+                }],
+            }],
+        }))?;
+        let transport = MockTransport::new(vec![
+            (200, GOLD_PASS),
+            (403, r#"{"reason":"accessDenied.invalidIp"}"#),
+        ]);
+        let client = Client::builder()
+            .transport(transport.clone())
+            .dev_base_url("http://127.0.0.1:9")
+            .restore(single_credential(), snapshot.clone())
+            .await?;
+
+        // the restored key is used without logging in..
+        client.get_goldpass().await?;
+        assert_eq!(transport.requests()[0].1.as_deref(), Some("Bearer restored"));
+        assert_eq!(client.export_session(), snapshot);
+
+        // ..until the API rejects it
+        assert!(matches!(client.get_goldpass().await, Err(APIError::LoginFailed(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_reinit() -> anyhow::Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // a developer portal that takes a while to refuse every login
+        let portal = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let dev_base_url = format!("http://{}", portal.local_addr()?);
+        let logins = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        tokio::spawn({
+            let logins = logins.clone();
+            async move {
+                while let Ok((mut stream, _)) = portal.accept().await {
+                    logins.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    let _ = stream.read(&mut [0; 4096]).await;
+                    tokio::time::sleep(Duration::from_millis(300)).await;
+                    let _ = stream
+                        .write_all(
+                            b"HTTP/1.1 500 Internal Server Error\r\ncontent-length: 0\r\n\r\n",
+                        )
+                        .await;
+                }
+            }
+        });
+
+        let invalid_ip = r#"{"reason":"accessDenied.invalidIp"}"#;
+        let transport = MockTransport::new(vec![
+            (403, invalid_ip),
+            (403, invalid_ip),
+            (403, invalid_ip),
+            (200, GOLD_PASS),
+        ])
+        .with_latency(Duration::from_millis(50));
+        let client = Client::builder()
+            .transport(transport)
+            .dev_base_url(&dev_base_url)
+            .coalesce_requests(false)
+            .restore(single_credential(), restored_snapshot())
+            .await?;
+
+        let rejected = futures::future::join_all((0..3).map(|_| client.get_goldpass()));
+        let waiting = async {
+            // sent while the re-login runs, so it waits for it instead of failing
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            client.get_goldpass().await
+        };
+        let (rejected, waiting) = tokio::join!(rejected, waiting);
+
+        assert!(rejected.iter().all(|result| matches!(
+            result,
+            Err(APIError::LoginFailed(LoginError::PortalUnavailable(_)))
+        )));
+        assert!(waiting.is_ok());
+        assert_eq!(logins.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_login_at() -> anyhow::Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // a developer portal stand-in that refuses every credential
+        let portal = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let base_url = format!("http://{}/", portal.local_addr()?);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = portal.accept().await {
+                let mut request = [0; 4096];
+                let read = stream.read(&mut request).await.unwrap_or_default();
+                let status = if request[..read].starts_with(b"POST /api/login ") {
+                    "403 Forbidden"
+                } else {
+                    "404 Not Found"
+                };
+                let _ = stream
+                    .write_all(format!("HTTP/1.1 {status}\r\ncontent-length: 0\r\n\r\n").as_bytes())
+                    .await;
+            }
+        });
+
+        let credential = &single_credential().0[0];
+        let error = DeveloperPortal::login_at(&base_url, credential).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<APIError>(),
+            Some(APIError::LoginFailed(LoginError::InvalidCredentials))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_reinit() -> anyhow::Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // a developer portal that takes a while to refuse every login
+        let portal = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let dev_base_url = format!("http://{}", portal.local_addr()?);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = portal.accept().await {
+                let _ = stream.read(&mut [0; 4096]).await;
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                let _ = stream
+                    .write_all(b"HTTP/1.1 500 Internal Server Error\r\ncontent-length: 0\r\n\r\n")
+                    .await;
+            }
+        });
+
+        let transport = MockTransport::new(vec![
+            (403, r#"{"reason":"accessDenied.invalidIp"}"#),
+            (200, GOLD_PASS),
+        ]);
+        let client = Client::builder()
+            .transport(transport)
+            .dev_base_url(&dev_base_url)
+            .coalesce_requests(false)
+            .ready_timeout(Duration::from_millis(500))
+            .restore(single_credential(), restored_snapshot())
+            .await?;
+
+        // the caller that started the re-login gives up on it halfway
+        let abandoned = tokio::time::timeout(Duration::from_millis(50), client.get_goldpass());
+        assert!(abandoned.await.is_err());
+        // the re-login finishes anyway, so later requests don't wait forever
+        client.get_goldpass().await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refresh() -> anyhow::Result<()> {
+        let transport = MockTransport::new(vec![(200, "203.0.113.7\n"), (200, "198.51.100.1")]);
+        let client = Client::builder()
+            .transport(transport.clone())
+            .dev_base_url("http://127.0.0.1:9")
+            .ip_lookup_url("http://ip.test")
+            .restore(single_credential(), restored_snapshot())
+            .await?;
+
+        // same address and no portal session to expire, nothing to do
+        client.refresh().await?;
+        // the address changed, so the account logs in again, which fails here
+        let error = client.refresh().await.unwrap_err().downcast::<APIError>()?;
+        assert!(matches!(error, APIError::LoginFailed(LoginError::PortalUnavailable(_))));
+        assert!(transport.requests().iter().all(|(url, _)| url == "http://ip.test/"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refresh_after_ip_change() -> anyhow::Result<()> {
+        let portal = FakePortal::start("203.0.113.7").await?;
+        let transport = MockTransport::new(vec![(200, "198.51.100.1"), (200, "198.51.100.1")]);
+        let client = Client::builder()
+            .transport(transport)
+            .dev_base_url(&portal.base_url)
+            .ip_lookup_url("http://ip.test")
+            .build(single_credential())
+            .await?;
+
+        // the address changed, so the account logs in again and keeps the new address
+        portal.set_ip("198.51.100.1");
+        client.refresh().await?;
+        assert_eq!(client.export_session().ip_address, "198.51.100.1/32");
+        // the keys were made for the new address, nothing left to do
+        client.refresh().await?;
+        assert_eq!(portal.logins(), ["user1", "user1"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_isolated_sessions() -> anyhow::Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // an API that hands out a cookie and records who sends it back
+        let api = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let base_url = format!("http://{}", api.local_addr()?);
+        let cookies = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        tokio::spawn({
+            let cookies = cookies.clone();
+            async move {
+                while let Ok((mut stream, _)) = api.accept().await {
+                    let mut request = vec![0; 4096];
+                    let read = stream.read(&mut request).await.unwrap_or_default();
+                    let request = String::from_utf8_lossy(&request[..read]).to_lowercase();
+                    cookies.lock().push(request.contains("cookie: session="));
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nset-cookie: session=1\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{GOLD_PASS}",
+                        GOLD_PASS.len()
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                }
+            }
+        });
+
+        let client =
+            || Client::builder().base_url(&base_url).build_with_tokens(vec!["token".to_owned()]);
+        let (first, second) = (client()?, client()?);
+        first.get_goldpass().await?;
+        first.get_goldpass().await?;
+        second.get_goldpass().await?;
+
+        // only the client that got the cookie sends it back
+        assert_eq!(*cookies.lock(), [false, true, false]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_partial_login() {
+        let credentials = Credentials::builder()
+            .add_credential("user1".to_owned(), "pass1".to_owned())
+            .add_credential("user2".to_owned(), "pass2".to_owned())
+            .build();
+        let result = Client::builder()
+            .dev_base_url("http://127.0.0.1:9")
+            .partial_login(true)
+            .build(credentials)
+            .await;
+        match result.map_err(|e| e.downcast::<APIError>()) {
+            Err(Ok(APIError::NoAccountsLoggedIn(report))) => {
+                let emails = report.failures.iter().map(|f| f.email.as_str()).collect::<Vec<_>>();
+                assert_eq!(emails, ["user1", "user2"]);
+                assert!(report.failures[0].error.contains("login request failed for user1"));
+                assert!(matches!(report.failures[0].reason, LoginError::PortalUnavailable(_)));
+            }
+            _ => panic!("expected every login to fail"),
+        }
+
+        // no credentials at all is an error rather than a panic
+        let result = Client::builder().build(Credentials::empty()).await;
+        assert!(matches!(
+            result.map_err(|e| e.downcast::<APIError>()),
+            Err(Ok(APIError::NoAccountsLoggedIn(report))) if report.is_empty()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_re_login_report() -> anyhow::Result<()> {
+        let portal = FakePortal::start("203.0.113.7").await?;
+        let credentials = Credentials::builder()
+            .add_credential("user1".to_owned(), "pass1".to_owned())
+            .add_credential("user2".to_owned(), "pass2".to_owned())
+            .build();
+        let client = Client::builder()
+            .transport(MockTransport::new(vec![(200, "203.0.113.7")]))
+            .dev_base_url(&portal.base_url)
+            .ip_lookup_url("http://ip.test")
+            .build(credentials)
+            .await?;
+        assert!(client.login_report().is_empty());
+        let keys = |client: &Client, email: &str| {
+            let session = client.export_session();
+            session.accounts.iter().find(|account| account.email == email).map(|a| a.keys.len())
+        };
+
+        // user1's password changed, user2 still gets fresh keys
+        portal.refuse("user1");
+        let error = client.reinit().await.unwrap_err();
+        assert!(matches!(error, APIError::LoginFailed(LoginError::InvalidCredentials)));
+        let mut logins = portal.logins();
+        logins.sort();
+        assert_eq!(logins, ["user1", "user2", "user2"]);
+        let report = client.login_report();
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].email, "user1");
+        assert_eq!(report.failures[0].reason, LoginError::InvalidCredentials);
+        // user1's keys are for the old address, so they leave the rotation
+        assert_eq!(keys(&client, "user1"), Some(0));
+        assert_eq!(keys(&client, "user2"), Some(DeveloperPortal::MAX_KEYS));
+
+        // until the account logs in again
+        portal.accept("user1");
+        client.refresh().await?;
+        assert_eq!(keys(&client, "user1"), Some(DeveloperPortal::MAX_KEYS));
+        assert_eq!(portal.logins().len(), 4);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_new_client() -> anyhow::Result<()> {
         let credentials = Credentials::builder();
@@ -379,7 +1565,7 @@ mod tests {
         let mut clan_rankings = CLIENT.get_clan_rankings(location::Local::UnitedStates).await?;
         println!("Time elapsed! {:?}", now.elapsed());
 
-        clan_rankings.items.sort_by(|a, b| a.clan_level.cmp(&b.clan_level));
+        clan_rankings.items.sort_by_key(|c| c.clan_level);
         for c in clan_rankings.items.iter().rev().take(100) {
             println!("{:>3}. {:>9} - {:>15} ({})", c.rank, c.tag, c.name, c.clan_level);
         }
@@ -425,7 +1611,7 @@ mod tests {
             CLIENT.get_versus_clan_rankings(location::Local::UnitedStates).await?;
         println!("Time elapsed! {:?}", now.elapsed());
 
-        versus_clan_rankings.items.sort_by(|a, b| a.clan_level.cmp(&b.clan_level));
+        versus_clan_rankings.items.sort_by_key(|c| c.clan_level);
         for c in versus_clan_rankings.items.iter().rev().take(100) {
             println!("{:>3}. {:>9} - {:>15} ({})", c.rank, c.tag, c.name, c.clan_level);
         }
@@ -443,7 +1629,7 @@ mod tests {
             CLIENT.get_versus_player_rankings(location::Local::UnitedStates).await?;
         println!("Time elapsed! {:?}", now.elapsed());
 
-        versus_player_rankings.items.sort_by(|a, b| a.exp_level.cmp(&b.exp_level));
+        versus_player_rankings.items.sort_by_key(|p| p.exp_level);
         for c in versus_player_rankings.items.iter().rev().take(100) {
             println!("{:>3}. {:>9} - {:>15} ({})", c.rank, c.tag, c.name, c.exp_level);
         }
@@ -563,8 +1749,8 @@ mod tests {
                         {
                             Ok(_) => break,
                             Err(e) => match e {
-                                APIError::NotFound => break,
-                                APIError::RequestThrottled => {
+                                APIError::NotFound(_) => break,
+                                APIError::RequestThrottled(_) => {
                                     *cloned_throttle_counter.lock().await += 1
                                 }
                                 _ => {
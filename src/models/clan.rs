@@ -79,12 +79,12 @@ impl Privacy {
     }
 }
 
-impl ToString for Privacy {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for Privacy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Open => "Anyone Can Join".to_string(),
-            Self::InviteOnly => "Invite Only".to_string(),
-            Self::Closed => "Closed".to_string(),
+            Self::Open => f.write_str("Anyone Can Join"),
+            Self::InviteOnly => f.write_str("Invite Only"),
+            Self::Closed => f.write_str("Closed"),
         }
     }
 }
@@ -138,16 +138,16 @@ impl WarFrequency {
     }
 }
 
-impl ToString for WarFrequency {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for WarFrequency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Unknown => "Not set".to_string(),
-            Self::Always => "Always".to_string(),
-            Self::MoreThanOncePerWeek => "Twice a week".to_string(),
-            Self::OncePerWeek => "Once a week".to_string(),
-            Self::LessThanOncePerWeek => "Rarely".to_string(),
-            Self::Never => "Never".to_string(),
-            Self::Any => "Any".to_string(),
+            Self::Unknown => f.write_str("Not set"),
+            Self::Always => f.write_str("Always"),
+            Self::MoreThanOncePerWeek => f.write_str("Twice a week"),
+            Self::OncePerWeek => f.write_str("Once a week"),
+            Self::LessThanOncePerWeek => f.write_str("Rarely"),
+            Self::Never => f.write_str("Never"),
+            Self::Any => f.write_str("Any"),
         }
     }
 }
@@ -213,14 +213,14 @@ impl Role {
     }
 }
 
-impl ToString for Role {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::NotMember => "Not Member".to_string(),
-            Self::Member => "Member".to_string(),
-            Self::Elder => "Elder".to_string(),
-            Self::CoLeader => "Co-Leader".to_string(),
-            Self::Leader => "Leader".to_string(),
+            Self::NotMember => f.write_str("Not Member"),
+            Self::Member => f.write_str("Member"),
+            Self::Elder => f.write_str("Elder"),
+            Self::CoLeader => f.write_str("Co-Leader"),
+            Self::Leader => f.write_str("Leader"),
         }
     }
 }
@@ -25,9 +25,11 @@ pub struct Location {
     Ord,
     ToPrimitive,
     FromPrimitive,
+    Default,
 )]
 #[repr(i32)]
 pub enum Local {
+    #[default]
     None = 0,
     Europe = 32_000_000,
     NorthAmerica = 32_000_001,
@@ -303,12 +305,6 @@ impl std::fmt::Display for Local {
     }
 }
 
-impl Default for Local {
-    fn default() -> Self {
-        Self::None
-    }
-}
-
 impl Local {
     #[must_use]
     pub fn from_i32(num: i32) -> Option<Self> {
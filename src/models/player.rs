@@ -55,11 +55,11 @@ impl WarPreference {
     }
 }
 
-impl ToString for WarPreference {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for WarPreference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::In => "in".to_string(),
-            Self::Out => "out".to_string(),
+            Self::In => f.write_str("in"),
+            Self::Out => f.write_str("out"),
         }
     }
 }
@@ -136,12 +136,12 @@ impl Village {
     }
 }
 
-impl ToString for Village {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for Village {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::HomeVillage => "Home Village".to_string(),
-            Self::BuilderBase => "Builder Base".to_string(),
-            Self::ClanCapital => "Clan Capital".to_string(),
+            Self::HomeVillage => f.write_str("Home Village"),
+            Self::BuilderBase => f.write_str("Builder Base"),
+            Self::ClanCapital => f.write_str("Clan Capital"),
         }
     }
 }
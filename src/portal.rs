@@ -0,0 +1,674 @@
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use base64::Engine;
+use ipnet::IpNet;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::ClientConfig,
+    credentials::Credential,
+    error::{APIError, LoginError},
+    paging::BASE64_ENGINE,
+    secret::Secret,
+};
+
+/// A logged in session on the developer portal, for managing an account's API keys without a
+/// browser.
+///
+/// # Example
+/// ```no_run
+/// use coc_rs::{
+///     credentials::Credential,
+///     portal::{DeveloperPortal, KeySpec},
+/// };
+///
+/// let credential = Credential::new("email".to_owned(), "password".to_owned());
+/// let portal = DeveloperPortal::login(&credential).await?;
+/// for key in portal.list_keys().await? {
+///     if key.name.starts_with("staging") {
+///         portal.revoke_key(&key.id).await?;
+///     }
+/// }
+/// let key = portal
+///     .create_key(&KeySpec::new("production").description("bot").cidr("203.0.113.7"))
+///     .await?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct DeveloperPortal {
+    session: reqwest::Client,
+    base_url: String,
+    response: LoginResponse,
+    logged_in_at: Instant,
+}
+
+impl DeveloperPortal {
+    pub const BASE_URL: &'static str = "https://developer.clashofclans.com";
+    /// How many keys the portal allows per account
+    pub const MAX_KEYS: usize = 10;
+    const KEY_CREATE_ENDPOINT: &'static str = "/api/apikey/create";
+    const KEY_LIST_ENDPOINT: &'static str = "/api/apikey/list";
+    const KEY_REVOKE_ENDPOINT: &'static str = "/api/apikey/revoke";
+    const LOGIN_ENDPOINT: &'static str = "/api/login";
+
+    /// Logs into the developer portal
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the request fails or the credential is invalid
+    pub async fn login(credential: &Credential) -> anyhow::Result<Self> {
+        Self::login_with_config(credential, &ClientConfig::default()).await
+    }
+
+    /// Logs into the developer portal at another base URL, e.g. a local stand-in
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the request fails or the credential is invalid
+    pub async fn login_at(base_url: &str, credential: &Credential) -> anyhow::Result<Self> {
+        let config = ClientConfig {
+            dev_base_url: base_url.trim_end_matches('/').to_owned(),
+            ..ClientConfig::default()
+        };
+        Self::login_with_config(credential, &config).await
+    }
+
+    /// Logs in with the base URL, timeout and user agent a [`Client`](crate::api::Client) was
+    /// built with
+    pub(crate) async fn login_with_config(
+        credential: &Credential,
+        config: &ClientConfig,
+    ) -> anyhow::Result<Self> {
+        let mut builder = reqwest::Client::builder().cookie_store(true);
+        if let Some(timeout) = config.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(user_agent) = &config.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        let logged_in_at = Instant::now();
+        let session = builder.build().context("failed to build the developer portal session")?;
+
+        let response = session
+            .post(format!("{}{}", config.dev_base_url, Self::LOGIN_ENDPOINT))
+            .json(credential)
+            .send()
+            .await
+            .map_err(|e| LoginError::PortalUnavailable(e.to_string()))
+            .and_then(|response| match response.status() {
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                    Err(LoginError::InvalidCredentials)
+                }
+                status if !status.is_success() => {
+                    Err(LoginError::PortalUnavailable(format!("status code {status}")))
+                }
+                _ => Ok(response),
+            })
+            .map_err(APIError::LoginFailed)
+            .context(format!("login request failed for {}", credential.email()))?
+            .json()
+            .await
+            .map_err(|e| APIError::LoginFailed(LoginError::UnexpectedResponse(e.to_string())))
+            .context(format!("login response failed to parse for {}", credential.email()))?;
+
+        Ok(Self { session, base_url: config.dev_base_url.clone(), response, logged_in_at })
+    }
+
+    /// The portal's response to logging in, which holds the IP address it saw the login from
+    #[must_use]
+    pub const fn login_response(&self) -> &LoginResponse {
+        &self.response
+    }
+
+    /// How long until the session or the temporary API token expires, whichever comes first.
+    /// Zero once either has.
+    #[must_use]
+    pub fn expires_in(&self) -> Duration {
+        let session = u64::try_from(self.response.session_expires_in_seconds).unwrap_or_default();
+        let token = &self.response.temporary_api_token;
+        let token = u64::try_from(token.exp - token.iat).unwrap_or_default();
+        // a lifetime the portal didn't send doesn't expire
+        let lifetime = [session, token].into_iter().filter(|&seconds| seconds > 0).min();
+
+        lifetime.map_or(Duration::MAX, |seconds| {
+            Duration::from_secs(seconds).saturating_sub(self.logged_in_at.elapsed())
+        })
+    }
+
+    /// Lists every key of the account
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the request fails
+    pub async fn list_keys(&self) -> anyhow::Result<Vec<Key>> {
+        let keys = self
+            .session
+            .post(format!("{}{}", self.base_url, Self::KEY_LIST_ENDPOINT))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context("list_keys request failed")?
+            .json::<KeyList>()
+            .await
+            .context("list_keys response failed to parse")?
+            .keys;
+
+        Ok(keys)
+    }
+
+    /// Creates a key, the portal allows at most [`Self::MAX_KEYS`] per account
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the request fails or the portal refuses to create it
+    pub async fn create_key(&self, spec: &KeySpec) -> anyhow::Result<Key> {
+        self.session
+            .post(format!("{}{}", self.base_url, Self::KEY_CREATE_ENDPOINT))
+            .json(spec)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context("create_key request failed")?
+            .json::<KeyResponse>()
+            .await
+            .context("create_key response failed to parse")?
+            .key
+            .context("create_key response has no key")
+    }
+
+    /// Revokes the key with the given id
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the request fails
+    pub async fn revoke_key(&self, key_id: &str) -> anyhow::Result<()> {
+        self.session
+            .post(format!("{}{}", self.base_url, Self::KEY_REVOKE_ENDPOINT))
+            .json(&serde_json::json!({ "id": key_id }))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context("revoke_key request failed")?
+            .json::<KeyResponse>()
+            .await
+            .context("revoke_key response failed to parse")?;
+
+        Ok(())
+    }
+
+    /// Renames a key, keeping its CIDR ranges and scopes.
+    ///
+    /// The portal can't edit keys, so this creates a new key and then revokes the old one. The
+    /// returned key has a new id and token, and the old token stops working. If revoking fails,
+    /// both keys are left in place and the error is returned.
+    ///
+    /// Creating the new key needs a free slot, so this fails on an account that already has
+    /// [`Self::MAX_KEYS`] keys, which accounts a [`Client`](crate::api::Client) logs into have by
+    /// default. Use [`Self::rename_key_in_place`] for those.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if creating the new key or revoking the old one fails
+    pub async fn rename_key(
+        &self,
+        key: &Key,
+        name: &str,
+        description: Option<&str>,
+    ) -> anyhow::Result<Key> {
+        let renamed = self.create_key(&Self::renamed(key, name, description)).await?;
+        self.revoke_key(&key.id).await.context(format!(
+            "created {} but failed to revoke {}, both keys exist now",
+            renamed.id, key.id
+        ))?;
+
+        Ok(renamed)
+    }
+
+    /// Renames a key like [`Self::rename_key`], but revokes the old key before creating the new
+    /// one, so that it works on an account without a free slot. If creating fails, the old key
+    /// is gone and the error is returned.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if revoking the old key or creating the new one fails
+    pub async fn rename_key_in_place(
+        &self,
+        key: &Key,
+        name: &str,
+        description: Option<&str>,
+    ) -> anyhow::Result<Key> {
+        self.revoke_key(&key.id).await?;
+        self.create_key(&Self::renamed(key, name, description))
+            .await
+            .context(format!("revoked {} but failed to create its replacement", key.id))
+    }
+
+    /// The spec of a key like `key`, with another name and description
+    fn renamed(key: &Key, name: &str, description: Option<&str>) -> KeySpec {
+        KeySpec {
+            name: name.to_owned(),
+            description: description
+                .map(ToOwned::to_owned)
+                .or_else(|| key.description.clone())
+                .unwrap_or_default(),
+            cidr_ranges: key.cidr_ranges.clone(),
+            scopes: key.scopes.clone(),
+        }
+    }
+}
+
+/// The name, description, allowed IP addresses and scopes of a key to create
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct KeySpec {
+    name: String,
+    description: String,
+    #[serde(rename = "cidrRanges")]
+    cidr_ranges: Vec<String>,
+    scopes: Vec<Scope>,
+}
+
+impl KeySpec {
+    /// Returns a spec for a key with the `clash` scope, no description and no CIDR ranges yet
+    #[must_use]
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            description: String::new(),
+            cidr_ranges: Vec::new(),
+            scopes: vec![Scope::Clash],
+        }
+    }
+
+    #[must_use]
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = description.to_owned();
+        self
+    }
+
+    /// Allows the key to be used from an IP address or CIDR range, e.g. `203.0.113.7` or
+    /// `203.0.113.0/24`
+    #[must_use]
+    pub fn cidr(mut self, cidr: &str) -> Self {
+        self.cidr_ranges.push(cidr.to_owned());
+        self
+    }
+
+    #[must_use]
+    pub fn scopes(mut self, scopes: &[Scope]) -> Self {
+        self.scopes = scopes.to_vec();
+        self
+    }
+}
+
+/// How a [`Client`](crate::api::Client) maintains an account's keys: how many it keeps, how it
+/// names the keys it creates, and which keys it may revoke or rotate.
+///
+/// A key is owned by the client if its name starts with `name_prefix` and its description
+/// contains `description_marker`. Keys that aren't owned are left alone, so that keys made by
+/// hand for other servers keep working.
+///
+/// The name and description templates may use these placeholders:
+/// - `{prefix}` and `{marker}`, the name prefix and description marker
+/// - `{email}`, the email of the account
+/// - `{ip}`, the IP addresses the key is created for, separated by commas
+/// - `{date}`, when the key is created, in RFC 3339
+///
+/// # Example
+/// ```no_run
+/// use coc_rs::{api::Client, portal::KeySettings};
+///
+/// let settings = KeySettings::new()
+///     .name_prefix("bot-eu")
+///     .description_marker("eu cluster")
+///     .description_template("{marker} key for {ip}, created on {date}")
+///     .key_count(6)
+///     .reserved_slots(2);
+/// let client = Client::builder()
+///     .key_settings(KeySettings::new().key_count(4))
+///     .key_settings_for("ops@example.com", settings)
+///     .build(credentials)
+///     .await?;
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeySettings {
+    name_prefix: String,
+    description_marker: String,
+    name_template: String,
+    description_template: String,
+    key_count: usize,
+    reserved_slots: usize,
+    manage_foreign_keys: bool,
+}
+
+impl Default for KeySettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeySettings {
+    /// Returns the settings keys have always been created with: as many keys as the portal
+    /// allows, named `coc-rs` and described as created by `coc.rs`
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            name_prefix: "coc-rs".to_owned(),
+            description_marker: "coc.rs".to_owned(),
+            name_template: "{prefix}".to_owned(),
+            description_template: "Created on {date} by {marker}".to_owned(),
+            key_count: DeveloperPortal::MAX_KEYS,
+            reserved_slots: 0,
+            manage_foreign_keys: false,
+        }
+    }
+
+    /// The prefix owned keys are recognized by
+    #[must_use]
+    pub fn name_prefix(mut self, name_prefix: &str) -> Self {
+        self.name_prefix = name_prefix.to_owned();
+        self
+    }
+
+    /// Included in the description of created keys, and what owned keys are recognized by
+    #[must_use]
+    pub fn description_marker(mut self, description_marker: &str) -> Self {
+        self.description_marker = description_marker.to_owned();
+        self
+    }
+
+    /// Name of created keys, defaults to `{prefix}`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the template doesn't start with `{prefix}`, since keys created with it wouldn't
+    /// be recognized as owned
+    #[must_use]
+    pub fn name_template(mut self, name_template: &str) -> Self {
+        assert!(name_template.starts_with("{prefix}"), "name_template must start with {{prefix}}");
+        self.name_template = name_template.to_owned();
+        self
+    }
+
+    /// Description of created keys, defaults to `Created on {date} by {marker}`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the template doesn't contain `{marker}`, since keys created with it wouldn't be
+    /// recognized as owned
+    #[must_use]
+    pub fn description_template(mut self, description_template: &str) -> Self {
+        assert!(
+            description_template.contains("{marker}"),
+            "description_template must contain {{marker}}"
+        );
+        self.description_template = description_template.to_owned();
+        self
+    }
+
+    /// How many keys to maintain, capped at [`DeveloperPortal::MAX_KEYS`] minus the reserved
+    /// slots. Owned keys beyond this count are revoked.
+    #[must_use]
+    pub const fn key_count(mut self, key_count: usize) -> Self {
+        self.key_count = key_count;
+        self
+    }
+
+    /// How many of the account's slots to leave free for keys made by hand
+    #[must_use]
+    pub const fn reserved_slots(mut self, reserved_slots: usize) -> Self {
+        self.reserved_slots = reserved_slots;
+        self
+    }
+
+    /// Whether keys the client doesn't own are used, revoked and rotated as well. Only enable
+    /// this if nothing else uses the accounts' keys.
+    #[must_use]
+    pub const fn manage_foreign_keys(mut self, manage_foreign_keys: bool) -> Self {
+        self.manage_foreign_keys = manage_foreign_keys;
+        self
+    }
+
+    /// Whether the key was created with these settings
+    #[must_use]
+    pub fn owns(&self, key: &Key) -> bool {
+        key.name.starts_with(&self.name_prefix)
+            && key.description.as_ref().is_some_and(|d| d.contains(&self.description_marker))
+    }
+
+    /// Whether the client may use, revoke and rotate the key
+    #[must_use]
+    pub fn manages(&self, key: &Key) -> bool {
+        self.manage_foreign_keys || self.owns(key)
+    }
+
+    /// How many keys are maintained once the portal's limit and the reserved slots are accounted
+    /// for
+    #[must_use]
+    pub fn max_keys(&self) -> usize {
+        self.key_count.min(DeveloperPortal::MAX_KEYS.saturating_sub(self.reserved_slots))
+    }
+
+    pub(crate) const fn reserved(&self) -> usize {
+        self.reserved_slots
+    }
+
+    /// The spec of a key to create for `cidrs` on the account of `email`, tagged so that it's
+    /// owned
+    pub(crate) fn spec(&self, email: &str, cidrs: &[String]) -> KeySpec {
+        let date = chrono::Utc::now().to_rfc3339();
+        let render = |template: &str| {
+            template
+                .replace("{prefix}", &self.name_prefix)
+                .replace("{marker}", &self.description_marker)
+                .replace("{email}", email)
+                .replace("{ip}", &cidrs.join(", "))
+                .replace("{date}", &date)
+        };
+        let spec = KeySpec::new(&render(&self.name_template))
+            .description(&render(&self.description_template));
+        cidrs.iter().fold(spec, |spec, cidr| spec.cidr(cidr))
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct KeyList {
+    keys: Vec<Key>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct LoginResponse {
+    pub status: Status,
+    #[serde(rename = "sessionExpiresInSeconds")]
+    pub session_expires_in_seconds: i32,
+    pub auth: Auth,
+    pub developer: Developer,
+    #[serde(rename = "temporaryAPIToken")]
+    pub temporary_api_token: TemporaryAPIToken,
+    #[serde(rename = "swaggerUrl")]
+    pub swagger_url: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Auth {
+    pub uid: String,
+    pub token: Secret,
+    pub ua: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Developer {
+    pub id: String,
+    pub name: String,
+    pub game: String,
+    pub email: String,
+    pub tier: String,
+    pub allowed_scopes: Option<String>,
+    pub max_cidrs: Option<String>,
+    #[serde(rename = "prevLoginTs")]
+    pub prev_login_ts: String,
+    #[serde(rename = "prevLoginIp")]
+    pub prev_login_ip: String,
+    #[serde(rename = "prevLoginUa")]
+    pub prev_login_ua: String,
+}
+
+// {"iss":"supercell","aud":"supercell:gameapi","jti":"6b59b631-e755-6c1f-b3be-a919949ee139","iat":1693633017,"exp":1693636617,"sub":"developer/54161cdf-f667-b806-56b7-4769c3e49c53","scopes":["clash"],"limits":[{"tier":"developer/bronze","type":"throttling"},{"cidrs":["108.30.223.213/32"],"typ
+// e":"client"},{"origins":["developer.clashofclans.com"],"type":"cors"}]}
+
+/// The claims of the JWT the portal hands out on login, which allows requests from the IP address
+/// the login came from for a while
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TemporaryAPIToken {
+    pub iss: String,
+    pub aud: String,
+    pub jti: String,
+    pub iat: i64,
+    pub exp: i64,
+    pub sub: String,
+    pub scopes: Vec<Scope>,
+    pub limits: Vec<Limit>,
+}
+
+/// The claims as they're encoded in the token
+#[derive(Deserialize)]
+struct Claims {
+    iss: String,
+    aud: String,
+    jti: String,
+    iat: i64,
+    exp: i64,
+    sub: String,
+    #[serde(default)]
+    scopes: Vec<Scope>,
+    #[serde(default)]
+    limits: Vec<Limit>,
+}
+
+impl TryFrom<String> for TemporaryAPIToken {
+    type Error = String;
+
+    /// Decodes the claims of a JWT, i.e. its second, base64 encoded part
+    fn try_from(jwt: String) -> Result<Self, Self::Error> {
+        let claims = jwt.split('.').nth(1).ok_or("the token isn't a JWT")?;
+        let decoded = BASE64_ENGINE
+            .decode(claims.trim_end_matches('='))
+            .map_err(|e| format!("failed to decode the token's claims: {e}"))?;
+        let Claims { iss, aud, jti, iat, exp, sub, scopes, limits } =
+            serde_json::from_slice(&decoded)
+                .map_err(|e| format!("unexpected claims in the token: {e}"))?;
+
+        Ok(Self { iss, aud, jti, iat, exp, sub, scopes, limits })
+    }
+}
+
+impl TemporaryAPIToken {
+    /// The limit on which IP addresses may use the token, which holds the address the portal saw
+    /// the login from
+    #[must_use]
+    pub fn client_limit(&self) -> Option<&Limit> {
+        self.limits.iter().find(|limit| limit.type_ == "client")
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Limit {
+    pub tier: Option<String>,
+    pub cidrs: Option<Vec<String>>,
+    pub origins: Option<Vec<String>>,
+    #[serde(rename = "type")]
+    pub type_: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Eq)]
+pub struct Key {
+    pub id: String,
+    #[serde(rename = "developerId")]
+    pub developer_id: String,
+    pub tier: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub origins: Option<Vec<String>>,
+    pub scopes: Vec<Scope>,
+    #[serde(rename = "cidrRanges")]
+    pub cidr_ranges: Vec<String>,
+    #[serde(rename = "validUntil")]
+    pub valid_until: Option<String>,
+    pub key: Secret,
+}
+
+impl Key {
+    /// The key's CIDR ranges as networks, a plain IP address is a network of just that address.
+    /// Ranges that don't parse are skipped.
+    #[must_use]
+    pub fn networks(&self) -> Vec<IpNet> {
+        self.cidr_ranges.iter().filter_map(|cidr| parse_cidr(cidr)).collect()
+    }
+
+    /// Whether the key may be used from `ip`, i.e. one of its CIDR ranges contains it
+    #[must_use]
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        self.networks().iter().any(|network| network.contains(&ip))
+    }
+
+    /// Whether the key may be used from every address of `network`
+    #[must_use]
+    pub fn covers(&self, network: &IpNet) -> bool {
+        self.networks().iter().any(|own| own.contains(network))
+    }
+}
+
+/// Parses a CIDR range such as `203.0.113.0/24` or `2001:db8::/32`, or a plain IP address
+pub(crate) fn parse_cidr(cidr: &str) -> Option<IpNet> {
+    let cidr = cidr.trim();
+    cidr.parse::<IpNet>().ok().or_else(|| cidr.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let desc = self.description.as_ref().map_or("None", |d| d);
+        writeln!(
+            f,
+            "Key {{ id: {}, name: {}, description: {}, key: {}, cidr_ranges: {} }}",
+            self.id,
+            self.name,
+            desc,
+            self.key,
+            self.cidr_ranges.join(", ")
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    #[serde(rename = "clash")]
+    #[default]
+    Clash,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Status {
+    pub code: i32,
+    pub message: String,
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct KeyResponse {
+    #[serde(rename = "status")]
+    _status: Status,
+    #[serde(rename = "sessionExpiresInSeconds")]
+    _session_expires_in_seconds: i64,
+    key: Option<Key>,
+}
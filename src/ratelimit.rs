@@ -0,0 +1,46 @@
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+/// A token bucket that refills at a fixed rate and holds at most one second's worth of tokens
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Returns a full bucket refilling `rate` tokens per second
+    pub(crate) fn new(rate: f64) -> Self {
+        let capacity = rate.max(1.0);
+        Self {
+            rate,
+            capacity,
+            state: Mutex::new(BucketState { tokens: capacity, last_refill: Instant::now() }),
+        }
+    }
+
+    /// Takes a token if one is available, otherwise returns how long until the next one is
+    pub(crate) fn try_acquire(&self) -> Result<(), Duration> {
+        let mut state = self.state.lock();
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+        state.tokens = elapsed.mul_add(self.rate, state.tokens).min(self.capacity);
+        state.last_refill = now;
+
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - state.tokens) / self.rate))
+        }
+    }
+}
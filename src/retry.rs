@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::error::APIError;
+
+/// Kinds of errors a [`RetryPolicy`] can retry
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RetryOn {
+    /// [`APIError::RequestThrottled`], status code of 429
+    Throttled,
+    /// [`APIError::UnknownError`], status code of 500
+    ServerError,
+    /// [`APIError::InMaintenance`], status code of 503
+    Maintenance,
+    /// [`APIError::RequestFailed`], the request never got a response
+    RequestFailed,
+}
+
+impl RetryOn {
+    const fn matches(self, error: &APIError) -> bool {
+        matches!(
+            (self, error),
+            (Self::Throttled, APIError::RequestThrottled(_))
+                | (Self::ServerError, APIError::UnknownError)
+                | (Self::Maintenance, APIError::InMaintenance(_))
+                | (Self::RequestFailed, APIError::RequestFailed(_))
+        )
+    }
+}
+
+/// How a [`Client`](crate::api::Client) retries requests that failed with a transient error.
+///
+/// The delay before each retry doubles from `base_delay` up to `max_delay`, with jitter so that
+/// concurrent requests don't retry in lockstep. A `Retry-After` header sent by the API takes
+/// precedence when `respect_retry_after` is enabled.
+///
+/// # Example
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use coc_rs::{
+///     api::Client,
+///     retry::{RetryOn, RetryPolicy},
+/// };
+///
+/// let policy = RetryPolicy::new()
+///     .max_attempts(5)
+///     .base_delay(Duration::from_secs(1))
+///     .retry_on(&[RetryOn::Throttled, RetryOn::Maintenance]);
+/// let client = Client::builder().retry_policy(policy).build(credentials).await?;
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    retry_on: Vec<RetryOn>,
+    respect_retry_after: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RetryPolicy {
+    /// Returns a policy making up to 3 attempts, waiting 500ms and then 1s, that retries every
+    /// kind of [`RetryOn`] and respects `Retry-After`
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            retry_on: vec![
+                RetryOn::Throttled,
+                RetryOn::ServerError,
+                RetryOn::Maintenance,
+                RetryOn::RequestFailed,
+            ],
+            respect_retry_after: true,
+        }
+    }
+
+    /// Returns a policy that never retries, which is what a [`Client`](crate::api::Client) uses
+    /// unless told otherwise
+    #[must_use]
+    pub fn none() -> Self {
+        Self { max_attempts: 1, ..Self::new() }
+    }
+
+    /// The total number of attempts, including the first one
+    #[must_use]
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// The delay before the first retry, doubled for every retry after it
+    #[must_use]
+    pub const fn base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// The longest the backoff can grow to
+    #[must_use]
+    pub const fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// The kinds of errors that are retried, every other error is returned immediately
+    #[must_use]
+    pub fn retry_on(mut self, retry_on: &[RetryOn]) -> Self {
+        self.retry_on = retry_on.to_vec();
+        self
+    }
+
+    /// Whether to wait as long as the API's `Retry-After` header asks instead of backing off
+    #[must_use]
+    pub const fn respect_retry_after(mut self, respect_retry_after: bool) -> Self {
+        self.respect_retry_after = respect_retry_after;
+        self
+    }
+
+    /// Whether a request that failed on its `attempt`th try (starting at 1) should be retried
+    pub(crate) fn should_retry(&self, attempt: u32, error: &APIError) -> bool {
+        attempt < self.max_attempts && self.retry_on.iter().any(|kind| kind.matches(error))
+    }
+
+    /// How long to wait before retrying a request that failed on its `attempt`th try
+    pub(crate) fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after.filter(|_| self.respect_retry_after) {
+            return retry_after;
+        }
+
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
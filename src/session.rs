@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+
+use crate::portal::Key;
+
+/// The keys a [`Client`](crate::api::Client) logged in with, so that a restarted process or
+/// another replica can reuse them instead of logging into every account again.
+///
+/// The snapshot holds the API keys in plain text, store it like you'd store the credentials.
+///
+/// # Example
+/// ```no_run
+/// use coc_rs::{api::Client, session::SessionSnapshot};
+///
+/// let snapshot = client.export_session();
+/// std::fs::write("session.json", serde_json::to_string(&snapshot)?)?;
+///
+/// // after a restart
+/// let snapshot = serde_json::from_str::<SessionSnapshot>(&std::fs::read_to_string("session.json")?)?;
+/// let client = Client::builder().restore(credentials, snapshot).await?;
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    /// The IP address the keys were created for
+    pub ip_address: String,
+    pub accounts: Vec<AccountSnapshot>,
+}
+
+/// The keys of one developer account
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AccountSnapshot {
+    pub email: String,
+    pub keys: Vec<Key>,
+}
+
+impl AccountSnapshot {
+    /// The keys that haven't expired yet, keys without an expiry never do
+    pub(crate) fn unexpired_keys(&self) -> Vec<Key> {
+        let now = chrono::Utc::now();
+        self.keys
+            .iter()
+            .filter(|key| {
+                key.valid_until
+                    .as_deref()
+                    .and_then(|valid_until| chrono::DateTime::parse_from_rfc3339(valid_until).ok())
+                    .is_none_or(|valid_until| valid_until > now)
+            })
+            .cloned()
+            .collect()
+    }
+}
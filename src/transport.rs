@@ -0,0 +1,63 @@
+use async_trait::async_trait;
+
+use crate::error::APIError;
+
+/// Sends every request a [`Client`](crate::api::Client) makes, which allows routing them through
+/// a proxy, a custom TLS setup or an in-memory fake in tests.
+///
+/// # Example
+/// ```no_run
+/// use async_trait::async_trait;
+/// use coc_rs::{api::Client, error::APIError, transport::HttpTransport};
+///
+/// #[derive(Debug)]
+/// struct Proxied(reqwest::Client);
+///
+/// #[async_trait]
+/// impl HttpTransport for Proxied {
+///     async fn execute(&self, request: reqwest::Request) -> Result<reqwest::Response, APIError> {
+///         Ok(self.0.execute(request).await?)
+///     }
+/// }
+///
+/// let proxy = reqwest::Proxy::all("socks5://127.0.0.1:1080")?;
+/// let transport = Proxied(reqwest::Client::builder().proxy(proxy).build()?);
+/// let client = Client::builder().transport(transport).build(credentials).await?;
+/// ```
+#[async_trait]
+pub trait HttpTransport: std::fmt::Debug + Send + Sync {
+    /// Sends the request and returns its response, regardless of the status code
+    async fn execute(&self, request: reqwest::Request) -> Result<reqwest::Response, APIError>;
+}
+
+/// The default transport, a thin wrapper around a [`reqwest::Client`]
+#[derive(Clone, Debug)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+impl ReqwestTransport {
+    #[must_use]
+    pub const fn new(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+impl Default for ReqwestTransport {
+    /// A [`reqwest::Client`] of its own with a cookie store, so that clients don't share cookies
+    /// or Clash of Stats sessions
+    fn default() -> Self {
+        let client = reqwest::Client::builder()
+            .cookie_store(true)
+            .build()
+            .expect("failed to build the HTTP client");
+        Self::new(client)
+    }
+}
+
+#[async_trait]
+impl HttpTransport for ReqwestTransport {
+    async fn execute(&self, request: reqwest::Request) -> Result<reqwest::Response, APIError> {
+        Ok(self.client.execute(request).await?)
+    }
+}
@@ -47,8 +47,8 @@ impl FromStr for LogicLong {
     }
 }
 
-impl ToString for LogicLong {
-    fn to_string(&self) -> String {
-        HASH_TAG_CODE_GENERATOR.to_code(*self)
+impl std::fmt::Display for LogicLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&HASH_TAG_CODE_GENERATOR.to_code(*self))
     }
 }
@@ -2,6 +2,7 @@ mod hash_tag_code_generator;
 mod logic_long;
 mod logic_long_to_code_converter_util;
 
+#[cfg_attr(not(feature = "extra"), allow(unused_imports))]
 pub use hash_tag_code_generator::HASH_TAG_CODE_GENERATOR;
 pub use logic_long::LogicLong;
 pub use logic_long_to_code_converter_util::LogicLongToCodeConverterUtil;